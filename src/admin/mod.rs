@@ -0,0 +1,221 @@
+/*
+    Admin http server (feature "admin"):
+    GET  /healthz, /readyz      probes, never need the token
+    GET  /status                json snapshot, see `Watcher::status`
+    POST /pause, /resume        {"work": "scanning" | "downloading", "watch_dir": optional}
+    POST /rescan
+    POST /orders/cancel, /orders/retry   {"order": path}
+    POST /orders/priority       {"order": path, "priority": n}
+    GET  /metrics               prometheus text format, with feature "metrics" too
+*/
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AdminConfig {
+    #[serde(default = "default_bind")]
+    pub bind: String,
+    ///If set, every endpoint but the probes needs `Authorization: Bearer <token>`
+    #[serde(default)]
+    pub token: Option<String>,
+}
+impl Default for AdminConfig {
+    fn default() -> Self {
+        Self {
+            bind: default_bind(),
+            token: None,
+        }
+    }
+}
+fn default_bind() -> String {
+    "127.0.0.1:8080".into()
+}
+
+#[cfg(feature = "admin")]
+pub use server::serve;
+
+#[cfg(feature = "admin")]
+mod server {
+    use super::AdminConfig;
+    use crate::{CircuitState, ConnectionState, CustomError, Result, Status, Watcher, Work};
+    use axum::{
+        extract::{Request, State},
+        http::{header, StatusCode},
+        middleware::{self, Next},
+        response::{IntoResponse, Response},
+        routing::{get, post},
+        Json, Router,
+    };
+    use serde::Deserialize;
+    use sha2::{Digest, Sha256};
+
+    #[derive(Clone)]
+    struct Admin {
+        watcher: &'static Watcher,
+        ///sha256 of the token, compared in constant time
+        token: Option<[u8; 32]>,
+    }
+
+    #[derive(Deserialize)]
+    struct PauseRequest {
+        work: Work,
+        watch_dir: Option<String>,
+    }
+
+    #[derive(Deserialize)]
+    struct OrderRequest {
+        order: String,
+    }
+
+    #[derive(Deserialize)]
+    struct PriorityRequest {
+        order: String,
+        priority: i32,
+    }
+
+    ///Serve admin endpoints until the listener fails
+    pub async fn serve(watcher: &'static Watcher, config: AdminConfig) -> Result<()> {
+        let admin = Admin {
+            watcher,
+            token: config.token.as_deref().map(token_hash),
+        };
+        let protected = Router::new()
+            .route("/status", get(status))
+            .route("/pause", post(pause))
+            .route("/resume", post(resume))
+            .route("/rescan", post(rescan))
+            .route("/orders/cancel", post(cancel))
+            .route("/orders/retry", post(retry))
+            .route("/orders/priority", post(priority));
+        #[cfg(feature = "metrics")]
+        let protected = protected.route("/metrics", get(metrics));
+        let protected =
+            protected.route_layer(middleware::from_fn_with_state(admin.clone(), authorize));
+        let app = Router::new()
+            .route("/healthz", get(|| async { "ok" }))
+            .route("/readyz", get(readyz))
+            .merge(protected)
+            .with_state(admin);
+        let listener = tokio::net::TcpListener::bind(&config.bind).await?;
+        tracing::info!(bind = %config.bind, "admin server listening");
+        axum::serve(listener, app)
+            .await
+            .map_err(|e| CustomError::Io(e.to_string()))
+    }
+
+    async fn authorize(State(admin): State<Admin>, request: Request, next: Next) -> Response {
+        if let Some(token) = &admin.token {
+            let bearer = request
+                .headers()
+                .get(header::AUTHORIZATION)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.strip_prefix("Bearer "));
+            if !bearer.is_some_and(|bearer| token_matches(token, bearer)) {
+                return StatusCode::UNAUTHORIZED.into_response();
+            }
+        }
+        next.run(request).await
+    }
+    fn token_hash(token: &str) -> [u8; 32] {
+        Sha256::digest(token.as_bytes()).into()
+    }
+    ///Hashes have the same length whatever the token, and every byte is compared,
+    /// so the time taken tells nothing about how much of the token was right
+    fn token_matches(expected: &[u8; 32], bearer: &str) -> bool {
+        let actual = token_hash(bearer);
+        expected
+            .iter()
+            .zip(actual.iter())
+            .fold(0u8, |diff, (a, b)| diff | (a ^ b))
+            == 0
+    }
+
+    ///Ready when some connection is usable and the last scan of every watched folder succeeded
+    async fn readyz(State(admin): State<Admin>) -> Response {
+        let status = admin.watcher.status().await;
+        let reasons = not_ready(&status);
+        if reasons.is_empty() {
+            (StatusCode::OK, "ready").into_response()
+        } else {
+            (StatusCode::SERVICE_UNAVAILABLE, Json(reasons)).into_response()
+        }
+    }
+    fn not_ready(status: &Status) -> Vec<String> {
+        let mut reasons = Vec::new();
+        if status.circuit == CircuitState::Open {
+            reasons.push("connection pool circuit is open".to_owned());
+        }
+        if let Some(e) = &status.auth_failure {
+            reasons.push(format!("authentication failed: {}", e));
+        }
+        if status
+            .connections
+            .iter()
+            .all(|c| c.state == ConnectionState::Degraded)
+        {
+            reasons.push("no healthy connections".to_owned());
+        }
+        if status.scans.is_empty() && !status.paused.scanning {
+            reasons.push("no scan finished yet".to_owned());
+        }
+        for scan in status.scans.iter() {
+            if let Some(e) = &scan.error {
+                reasons.push(format!("last scan of {} failed: {}", scan.watch_dir, e));
+            }
+        }
+        reasons
+    }
+
+    async fn status(State(admin): State<Admin>) -> Json<Status> {
+        Json(admin.watcher.status().await)
+    }
+    #[cfg(feature = "metrics")]
+    async fn metrics(State(admin): State<Admin>) -> impl IntoResponse {
+        (
+            [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+            admin.watcher.metrics().await,
+        )
+    }
+    async fn pause(State(admin): State<Admin>, Json(req): Json<PauseRequest>) -> StatusCode {
+        admin.watcher.pause(req.work, req.watch_dir.as_deref());
+        StatusCode::NO_CONTENT
+    }
+    async fn resume(State(admin): State<Admin>, Json(req): Json<PauseRequest>) -> StatusCode {
+        admin.watcher.resume(req.work, req.watch_dir.as_deref());
+        StatusCode::NO_CONTENT
+    }
+    async fn rescan(State(admin): State<Admin>) -> StatusCode {
+        admin.watcher.rescan();
+        StatusCode::NO_CONTENT
+    }
+    async fn cancel(State(admin): State<Admin>, Json(req): Json<OrderRequest>) -> StatusCode {
+        found(admin.watcher.cancel(&req.order))
+    }
+    async fn retry(State(admin): State<Admin>, Json(req): Json<OrderRequest>) -> StatusCode {
+        found(admin.watcher.retry(&req.order).await)
+    }
+    async fn priority(State(admin): State<Admin>, Json(req): Json<PriorityRequest>) -> StatusCode {
+        found(admin.watcher.set_priority(&req.order, req.priority).await)
+    }
+    ///Controls of an order answer 404 when there is no such order in the right state
+    fn found(done: bool) -> StatusCode {
+        if done {
+            StatusCode::NO_CONTENT
+        } else {
+            StatusCode::NOT_FOUND
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn only_the_exact_token_matches() {
+            let expected = token_hash("secret");
+            assert!(token_matches(&expected, "secret"));
+            assert!(!token_matches(&expected, "secreT"));
+            assert!(!token_matches(&expected, "secret "));
+            assert!(!token_matches(&expected, ""));
+        }
+    }
+}
@@ -0,0 +1,274 @@
+/*
+    Audit trail of transfers, kept apart from diagnostic logging:
+    every file received or sent and every remote entry deleted gets one line
+*/
+use crate::{Result, Rotation};
+use chrono::{DateTime, Local, Utc};
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::mpsc;
+use std::thread::JoinHandle;
+use tokio::sync::oneshot;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum AuditFormat {
+    ///wu-ftpd/ProFTPD xferlog lines; no local path and checksum, deletes have direction `d`
+    Xferlog,
+    ///One json object per line with every field of `AuditRecord`
+    #[default]
+    Jsonl,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AuditConfig {
+    pub dir: String,
+    ///File name, the date of its period is appended
+    #[serde(default = "default_audit_prefix")]
+    pub prefix: String,
+    #[serde(default)]
+    pub format: AuditFormat,
+    #[serde(default)]
+    pub rotation: Rotation,
+    ///Sync every record to disk before the transfer is reported done
+    #[serde(default = "default_fsync")]
+    pub fsync: bool,
+}
+fn default_audit_prefix() -> String {
+    "ftp_sync_audit.log".into()
+}
+fn default_fsync() -> bool {
+    true
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditAction {
+    Received,
+    ///Uploaded to the server
+    Sent,
+    RemoteDeleted,
+}
+
+/// Single audited transfer or remote delete
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AuditRecord {
+    ///RFC 3339, UTC
+    pub time: String,
+    pub action: AuditAction,
+    pub host: String,
+    pub user: String,
+    pub remote_path: String,
+    pub local_path: Option<String>,
+    pub size: Option<usize>,
+    pub duration_ms: Option<u64>,
+    ///sha256 of the received file
+    pub checksum: Option<String>,
+    ///Set when the transfer or delete failed
+    pub error: Option<String>,
+}
+impl AuditRecord {
+    pub fn new(action: AuditAction, host: &str, user: &str, remote_path: &str) -> Self {
+        Self {
+            time: Utc::now().to_rfc3339(),
+            action,
+            host: host.to_owned(),
+            user: user.to_owned(),
+            remote_path: remote_path.to_owned(),
+            local_path: None,
+            size: None,
+            duration_ms: None,
+            checksum: None,
+            error: None,
+        }
+    }
+    ///Time of the record, now if it cannot be parsed
+    fn timestamp(&self) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(&self.time)
+            .map(|time| time.with_timezone(&Utc))
+            .unwrap_or_else(|_| Utc::now())
+    }
+    ///`current-time transfer-time remote-host file-size filename transfer-type
+    /// special-action-flag direction access-mode username service-name
+    /// authentication-method authenticated-user-id completion-status`
+    fn to_xferlog(&self) -> String {
+        let direction = match self.action {
+            AuditAction::Received => 'i',
+            AuditAction::Sent => 'o',
+            AuditAction::RemoteDeleted => 'd',
+        };
+        format!(
+            "{} {} {} {} {} b _ {} r {} ftp 0 * {}",
+            self.timestamp()
+                .with_timezone(&Local)
+                .format("%a %b %e %H:%M:%S %Y"),
+            //whole seconds, at least 1 as wu-ftpd did
+            self.duration_ms.map_or(0, |ms| ms.div_ceil(1000).max(1)),
+            self.host,
+            self.size.unwrap_or_default(),
+            //fields are space separated
+            self.remote_path.replace(char::is_whitespace, "_"),
+            direction,
+            self.user,
+            if self.error.is_none() { 'c' } else { 'i' }
+        )
+    }
+}
+
+/// Line waiting for the writer thread; `synced` is told once it is on disk (fsync only)
+struct AuditLine {
+    period: String,
+    line: String,
+    synced: Option<oneshot::Sender<()>>,
+}
+
+/// Append-only audit trail, a new file is started every `rotation` period;
+/// Lines are written by a thread of their own, so recording never blocks async tasks
+#[derive(Debug)]
+pub struct AuditLog {
+    config: AuditConfig,
+    writer: Option<mpsc::Sender<AuditLine>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl AuditLog {
+    ///Create audit folder and start the writer thread; files are opened on first record
+    pub fn open(config: &AuditConfig) -> Result<Self> {
+        std::fs::create_dir_all(&config.dir)?;
+        let (writer, lines) = mpsc::channel();
+        let thread = std::thread::Builder::new().name("audit".into()).spawn({
+            let config = config.to_owned();
+            move || AuditLog::write_lines(config, lines)
+        })?;
+        Ok(Self {
+            config: config.to_owned(),
+            writer: Some(writer),
+            thread: Some(thread),
+        })
+    }
+    ///Append record, waiting until it is synced to disk if configured;
+    /// failures are reported but never stop the engine
+    pub async fn record(&self, record: AuditRecord) {
+        let line = match self.config.format {
+            AuditFormat::Xferlog => Ok(record.to_xferlog()),
+            AuditFormat::Jsonl => serde_json::to_string(&record),
+        };
+        let line = match line {
+            Ok(line) => line,
+            Err(e) => {
+                tracing::error!(dir = %self.config.dir, error = ?e, "failed to write audit record");
+                return;
+            }
+        };
+        let (synced, on_disk) = match self.config.fsync {
+            true => {
+                let (synced, on_disk) = oneshot::channel();
+                (Some(synced), Some(on_disk))
+            }
+            false => (None, None),
+        };
+        let line = AuditLine {
+            period: self.period(record.timestamp()),
+            line,
+            synced,
+        };
+        let sent = self
+            .writer
+            .as_ref()
+            .is_some_and(|writer| writer.send(line).is_ok());
+        if !sent {
+            tracing::error!(dir = %self.config.dir, "audit writer stopped, record is lost");
+            return;
+        }
+        if let Some(on_disk) = on_disk {
+            on_disk.await.ok();
+        }
+    }
+    ///Path of the file records of current period go to
+    pub fn current_path(&self) -> PathBuf {
+        path_for(&self.config, &self.period(Utc::now()))
+    }
+    ///Writer thread: append every line to the file of its period, until the audit log is dropped
+    fn write_lines(config: AuditConfig, lines: mpsc::Receiver<AuditLine>) {
+        let mut current: Option<(String, std::fs::File)> = None;
+        for line in lines {
+            let res = AuditLog::write_line(&config, &mut current, &line);
+            if let Err(e) = res {
+                tracing::error!(dir = %config.dir, error = ?e, "failed to write audit record");
+            }
+            if let Some(synced) = line.synced {
+                synced.send(()).ok();
+            }
+        }
+    }
+    fn write_line(
+        config: &AuditConfig,
+        current: &mut Option<(String, std::fs::File)>,
+        line: &AuditLine,
+    ) -> std::io::Result<()> {
+        if current
+            .as_ref()
+            .is_none_or(|(period, _)| *period != line.period)
+        {
+            let file = std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path_for(config, &line.period))?;
+            *current = Some((line.period.to_owned(), file));
+        }
+        let (_, file) = current.as_mut().expect("audit file is open");
+        file.write_all(format!("{}\n", line.line).as_bytes())?;
+        if config.fsync {
+            file.sync_data()?;
+        }
+        Ok(())
+    }
+    fn period(&self, time: DateTime<Utc>) -> String {
+        match self.config.rotation {
+            Rotation::Minutely => time.format("%Y-%m-%d-%H-%M").to_string(),
+            Rotation::Hourly => time.format("%Y-%m-%d-%H").to_string(),
+            Rotation::Daily => time.format("%Y-%m-%d").to_string(),
+            Rotation::Never => String::new(),
+        }
+    }
+}
+
+impl Drop for AuditLog {
+    fn drop(&mut self) {
+        drop(self.writer.take());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn path_for(config: &AuditConfig, period: &str) -> PathBuf {
+    let name = if period.is_empty() {
+        config.prefix.to_owned()
+    } else {
+        format!("{}.{}", config.prefix, period)
+    };
+    PathBuf::from(&config.dir).join(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn xferlog_line_has_the_time_of_the_record() {
+        let mut record = AuditRecord::new(AuditAction::Sent, "host:21", "user", "/in/a b.csv");
+        record.time = "2024-03-05T10:20:30+00:00".into();
+        record.size = Some(7);
+        record.duration_ms = Some(1500);
+        let time = DateTime::parse_from_rfc3339(&record.time)
+            .unwrap()
+            .with_timezone(&Local)
+            .format("%a %b %e %H:%M:%S %Y");
+        assert_eq!(
+            record.to_xferlog(),
+            format!("{} 2 host:21 7 /in/a_b.csv b _ o r user ftp 0 * c", time)
+        );
+    }
+}
@@ -0,0 +1,238 @@
+use crate::config::ReconnectConfig;
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+
+/// Per-connection reconnect schedule: exponential backoff with random jitter
+#[derive(Debug, Clone, Default)]
+pub struct Backoff {
+    failures: u32,
+    next_attempt: Option<Instant>,
+}
+impl Backoff {
+    ///Whether the next reconnect attempt is allowed already
+    pub fn is_due(&self) -> bool {
+        self.next_attempt.is_none_or(|at| Instant::now() >= at)
+    }
+    pub fn failures(&self) -> u32 {
+        self.failures
+    }
+    ///Register failed attempt and schedule the next one
+    pub fn fail(&mut self, config: &ReconnectConfig) {
+        self.failures += 1;
+        let exp = config
+            .multiplier
+            .powi(self.failures.saturating_sub(1) as i32);
+        let delay_ms = (config.initial_backoff_ms as f64 * exp).min(config.max_backoff_ms as f64);
+        //spread attempts of different connections in [delay * (1 - jitter), delay * (1 + jitter)]
+        let jitter = delay_ms * config.jitter * (fastrand::f64() * 2.0 - 1.0);
+        let delay = Duration::from_millis((delay_ms + jitter).max(0.0) as u64);
+        self.next_attempt = Some(Instant::now() + delay);
+    }
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CircuitState {
+    ///Connections are checked out normally
+    Closed,
+    ///Server considered down: no checkout, no reconnects until cool-down passes
+    Open,
+    ///Cool-down passed: a single reconnect probe decides whether to close or re-open
+    HalfOpen,
+}
+
+/// Pool-wide circuit breaker fed with results of reconnect attempts
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    state: CircuitState,
+    consecutive_failures: u32,
+    opened_at: Option<Instant>,
+    probe_in_flight: bool,
+    change: Option<(CircuitState, CircuitState)>,
+    failure_threshold: u32,
+    open_duration: Duration,
+}
+impl CircuitBreaker {
+    pub fn new(config: &ReconnectConfig) -> Self {
+        Self {
+            state: CircuitState::Closed,
+            consecutive_failures: 0,
+            opened_at: None,
+            probe_in_flight: false,
+            change: None,
+            failure_threshold: config.breaker_failure_threshold,
+            open_duration: Duration::from_secs(config.breaker_open_sec),
+        }
+    }
+    ///Current state, moving Open => HalfOpen once cool-down is over
+    pub fn state(&mut self) -> CircuitState {
+        if self.state == CircuitState::Open
+            && self
+                .opened_at
+                .is_some_and(|at| at.elapsed() >= self.open_duration)
+        {
+            self.transition(CircuitState::HalfOpen);
+        }
+        self.state
+    }
+    ///Whether connections may be handed out to workers
+    pub fn allows_checkout(&mut self) -> bool {
+        self.state() == CircuitState::Closed
+    }
+    ///Whether a reconnect attempt may be made now;
+    /// In half-open state only one probe is allowed at a time
+    pub fn allows_reconnect(&mut self) -> bool {
+        match self.state() {
+            CircuitState::Closed => true,
+            CircuitState::Open => false,
+            CircuitState::HalfOpen if self.probe_in_flight => false,
+            CircuitState::HalfOpen => {
+                self.probe_in_flight = true;
+                true
+            }
+        }
+    }
+    pub fn record_success(&mut self) {
+        self.consecutive_failures = 0;
+        self.probe_in_flight = false;
+        self.transition(CircuitState::Closed);
+    }
+    pub fn record_failure(&mut self) {
+        self.consecutive_failures += 1;
+        self.probe_in_flight = false;
+        if self.state == CircuitState::HalfOpen
+            || self.consecutive_failures >= self.failure_threshold
+        {
+            self.opened_at = Some(Instant::now());
+            self.transition(CircuitState::Open);
+        }
+    }
+    ///Probe ended without telling whether the server is up (e.g. credentials were rejected);
+    /// The next reconnect may probe again
+    pub fn cancel_probe(&mut self) {
+        self.probe_in_flight = false;
+    }
+    ///Take the last state change (from, to) that was not reported yet
+    pub fn take_change(&mut self) -> Option<(CircuitState, CircuitState)> {
+        self.change.take()
+    }
+    fn transition(&mut self, to: CircuitState) {
+        if self.state == to {
+            return;
+        }
+        let from = self.change.map_or(self.state, |(from, _)| from);
+        self.state = to;
+        self.change = Some((from, to));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(threshold: u32, open_sec: u64) -> ReconnectConfig {
+        ReconnectConfig {
+            breaker_failure_threshold: threshold,
+            breaker_open_sec: open_sec,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn opens_after_threshold_of_consecutive_failures() {
+        let mut breaker = CircuitBreaker::new(&config(3, 60));
+        breaker.record_failure();
+        breaker.record_failure();
+        assert_eq!(breaker.state(), CircuitState::Closed);
+        assert!(breaker.take_change().is_none());
+        breaker.record_failure();
+        assert_eq!(breaker.state(), CircuitState::Open);
+        assert!(!breaker.allows_checkout());
+        assert!(!breaker.allows_reconnect());
+        assert_eq!(
+            breaker.take_change(),
+            Some((CircuitState::Closed, CircuitState::Open))
+        );
+    }
+
+    #[test]
+    fn success_resets_failure_count() {
+        let mut breaker = CircuitBreaker::new(&config(2, 60));
+        breaker.record_failure();
+        breaker.record_success();
+        breaker.record_failure();
+        assert_eq!(breaker.state(), CircuitState::Closed);
+        assert!(breaker.allows_checkout());
+        assert!(breaker.allows_reconnect());
+    }
+
+    #[test]
+    fn half_open_allows_single_probe_and_closes_on_success() {
+        let mut breaker = CircuitBreaker::new(&config(1, 0));
+        breaker.record_failure();
+        assert_eq!(breaker.state(), CircuitState::HalfOpen);
+        //changes not taken in between are reported as one
+        assert_eq!(
+            breaker.take_change(),
+            Some((CircuitState::Closed, CircuitState::HalfOpen))
+        );
+        assert!(!breaker.allows_checkout());
+        assert!(breaker.allows_reconnect());
+        assert!(!breaker.allows_reconnect());
+        breaker.record_success();
+        assert_eq!(breaker.state(), CircuitState::Closed);
+        assert!(breaker.allows_checkout());
+        assert_eq!(
+            breaker.take_change(),
+            Some((CircuitState::HalfOpen, CircuitState::Closed))
+        );
+    }
+
+    #[test]
+    fn failed_probe_reopens() {
+        let mut breaker = CircuitBreaker::new(&config(5, 60));
+        for _ in 0..5 {
+            breaker.record_failure();
+        }
+        breaker.opened_at = Some(Instant::now() - Duration::from_secs(61));
+        assert_eq!(breaker.state(), CircuitState::HalfOpen);
+        breaker.take_change();
+        assert!(breaker.allows_reconnect());
+        breaker.record_failure();
+        assert_eq!(breaker.state(), CircuitState::Open);
+        assert_eq!(
+            breaker.take_change(),
+            Some((CircuitState::HalfOpen, CircuitState::Open))
+        );
+    }
+
+    #[test]
+    fn cancelled_probe_allows_next_one() {
+        let mut breaker = CircuitBreaker::new(&config(1, 0));
+        breaker.record_failure();
+        assert!(breaker.allows_reconnect());
+        breaker.cancel_probe();
+        assert_eq!(breaker.state(), CircuitState::HalfOpen);
+        assert!(breaker.allows_reconnect());
+    }
+
+    #[test]
+    fn backoff_waits_after_failure_until_reset() {
+        let config = ReconnectConfig {
+            jitter: 0.0,
+            ..Default::default()
+        };
+        let mut backoff = Backoff::default();
+        assert!(backoff.is_due());
+        backoff.fail(&config);
+        backoff.fail(&config);
+        assert_eq!(backoff.failures(), 2);
+        assert!(!backoff.is_due());
+        backoff.reset();
+        assert!(backoff.is_due());
+        assert_eq!(backoff.failures(), 0);
+    }
+}
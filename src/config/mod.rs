@@ -1,18 +1,219 @@
 use serde::{Deserialize, Serialize};
+use std::fmt;
 use std::path::PathBuf;
 
+use crate::admin::AdminConfig;
+use crate::audit::AuditConfig;
+use crate::filter::EntryFilter;
+use crate::hooks::CommandHook;
+use crate::logger::LogConfig;
+use crate::readiness::ReadinessConfig;
+use crate::scheduler::SchedulerConfig;
+use crate::NamePattern;
 use crate::Result;
 #[derive(Serialize, Deserialize, Clone)]
 pub struct Config {
     pub host: String,
     pub port: String,
-    dirs_to_watch: Vec<String>,
+    #[serde(deserialize_with = "deserialize_watch_dirs")]
+    dirs_to_watch: Vec<WatchDir>,
     pub local_folder: String,
     pub user: String,
     pub pass: String,
     pub connections: usize,
+    #[serde(default = "default_scan_connections")]
+    pub scan_connections: usize,
     conn_healthcheck_rate_sec: u16,
-    pub ready_flag_file_ext: String
+    #[serde(default)]
+    pub recycle: RecycleConfig,
+    #[serde(default)]
+    pub reconnect: ReconnectConfig,
+    #[serde(default)]
+    pub healthcheck: HealthCheck,
+    #[serde(default)]
+    readiness: Option<ReadinessConfig>,
+    pub ready_flag_file_ext: String,
+    ///File keeping track of orders fetched in copy and mirror modes
+    #[serde(default)]
+    state_file: Option<String>,
+    #[serde(default)]
+    pub retry: RetryPolicy,
+    #[serde(default)]
+    pub parallel: ParallelConfig,
+    #[serde(default)]
+    pub scheduler: SchedulerConfig,
+    ///Events kept for every subscriber before the slowest one starts missing them
+    #[serde(default = "default_event_buffer")]
+    pub event_buffer: usize,
+    ///How often progress of running orders is reported
+    #[serde(default = "default_progress_interval")]
+    pub progress_interval_ms: u64,
+    #[serde(default)]
+    pub log: LogConfig,
+    ///Admin http server, served by `serve_admin` (feature "admin")
+    #[serde(default)]
+    pub admin: AdminConfig,
+    ///Trail of received files and remote deletes, off if not set
+    #[serde(default)]
+    pub audit: Option<AuditConfig>,
+    ///Shell commands run on order events
+    #[serde(default)]
+    pub hooks: Vec<CommandHook>,
+    ///Journal of order lifecycle, replayed on startup
+    #[serde(default)]
+    journal_file: Option<String>,
+    ///Local folders whose orders are sent to the server
+    #[serde(default)]
+    upload_dirs: Vec<UploadDir>,
+}
+fn default_scan_connections() -> usize {
+    1
+}
+fn default_event_buffer() -> usize {
+    256
+}
+fn default_progress_interval() -> u64 {
+    1000
+}
+
+/// What happens to remote data once an order was downloaded
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SyncMode {
+    ///Delete remote files and folders after download (and verification)
+    #[default]
+    Move,
+    ///Keep remote copy, remember fetched orders so they are not downloaded again
+    Copy,
+    ///Keep local tree identical to remote one, including deletions
+    Mirror,
+    ///Rename the order into the remote archive folder
+    Archive,
+}
+
+/// Remote root folder whose subfolders (or files, `depth` levels down) are orders
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct WatchDir {
+    pub path: String,
+    pub sync_mode: SyncMode,
+    ///Archive folder name, inside the watched folder (archive mode)
+    pub archive_dir: String,
+    ///Share of downloads the folder gets when scheduler uses fair share
+    pub weight: u32,
+    ///Files of orders that are not downloaded
+    pub filter: EntryFilter,
+    ///Level at which orders are found: 1 - direct children, 3 - `path/<customer>/<date>/<order>`
+    pub depth: usize,
+    ///Every file (at `depth`) is an order of its own, folders at that level are ignored
+    pub file_orders: bool,
+    ///Order is ready when `<order name><companion>` (e.g. ".done") is present next to it;
+    /// The companion file is removed or archived along with the order
+    pub companion: Option<String>,
+    ///Readiness of this folder's orders, overrides the global one
+    pub readiness: Option<ReadinessConfig>,
+}
+impl Default for WatchDir {
+    fn default() -> Self {
+        Self {
+            path: String::new(),
+            sync_mode: SyncMode::default(),
+            archive_dir: "processed".into(),
+            weight: 1,
+            filter: EntryFilter::default(),
+            depth: 1,
+            file_orders: false,
+            companion: None,
+            readiness: None,
+        }
+    }
+}
+impl WatchDir {
+    pub fn new(path: &str) -> Self {
+        Self {
+            path: path.to_owned(),
+            ..Default::default()
+        }
+    }
+    ///Full remote path of the archive folder
+    pub fn get_archive_path(&self) -> String {
+        format!("{}/{}", self.path, self.archive_dir)
+    }
+    ///Whether orders of this folder are not checked by the global readiness strategy
+    pub fn has_own_readiness(&self) -> bool {
+        self.readiness.is_some() || self.companion.is_some() || self.file_orders
+    }
+}
+/// What happens to a local order once it was uploaded
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum AfterUpload {
+    ///Keep local copy, remember uploaded orders so they are not sent again
+    #[default]
+    Keep,
+    Delete,
+    ///Move the order into the local archive folder
+    Archive,
+}
+
+/// Local folder whose subfolders are orders to be uploaded into `remote_path`
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct UploadDir {
+    pub local_path: String,
+    pub remote_path: String,
+    #[serde(default)]
+    pub after_upload: AfterUpload,
+    ///Archive folder name, inside `local_path` (archive mode)
+    #[serde(default = "default_upload_archive_dir")]
+    pub archive_dir: String,
+    ///Flag files, uploaded after all other files; default: files with `ready_flag_file_ext` extension
+    #[serde(default)]
+    pub flag: Option<NamePattern>,
+    ///Readiness of local orders; default: same strategy as downloads
+    #[serde(default)]
+    pub readiness: Option<ReadinessConfig>,
+}
+impl UploadDir {
+    ///Full local path of the archive folder
+    pub fn get_archive_path(&self) -> String {
+        format!("{}/{}", self.local_path, self.archive_dir)
+    }
+}
+fn default_upload_archive_dir() -> String {
+    "uploaded".into()
+}
+
+//watch dir is either a plain path or an object with per-dir settings
+fn deserialize_watch_dirs<'de, D>(deserializer: D) -> std::result::Result<Vec<WatchDir>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Repr {
+        Path(String),
+        Full(Box<WatchDir>),
+    }
+    let dirs: Vec<Repr> = Vec::deserialize(deserializer)?;
+    Ok(dirs
+        .into_iter()
+        .map(|dir| match dir {
+            Repr::Path(path) => WatchDir::new(&path),
+            Repr::Full(dir) => *dir,
+        })
+        .collect())
+}
+//password is never printed
+impl fmt::Debug for Config {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Config")
+            .field("host", &self.host)
+            .field("port", &self.port)
+            .field("user", &self.user)
+            .field("dirs_to_watch", &self.dirs_to_watch)
+            .field("local_folder", &self.local_folder)
+            .finish_non_exhaustive()
+    }
 }
 impl Config {
     //read config file in root dir
@@ -23,6 +224,10 @@ impl Config {
         let config = serde_json::from_reader(rdr)?;
         Ok(config)
     }
+    ///Label of this server account in metrics: user@host:port
+    pub fn get_profile(&self) -> String {
+        format!("{}@{}", self.user, self.get_hostname())
+    }
     pub fn get_hostname(&self) -> String {
         let mut host = self.host.to_owned();
         host.push(':');
@@ -30,14 +235,210 @@ impl Config {
         host
     }
     pub fn get_download_target_path(&self, root: &str, ftp_path: &str) -> String {
-        let path = ftp_path.replace(root, &self.local_folder);
-        println!("{}", path);
-        path
+        ftp_path.replace(root, &self.local_folder)
+    }
+    ///Whether both configs log in to the same server with the same credentials
+    pub fn same_credentials(&self, other: &Config) -> bool {
+        self.get_hostname() == other.get_hostname()
+            && self.user == other.user
+            && self.pass == other.pass
     }
     pub fn get_dirs_to_watch(&self) -> Vec<String> {
+        self.dirs_to_watch
+            .iter()
+            .map(|d| d.path.to_owned())
+            .collect()
+    }
+    pub fn get_watch_dirs(&self) -> Vec<WatchDir> {
         self.dirs_to_watch.to_owned()
     }
+    pub fn get_upload_dirs(&self) -> Vec<UploadDir> {
+        self.upload_dirs.to_owned()
+    }
+    pub fn get_upload_readiness(&self, dir: &UploadDir) -> ReadinessConfig {
+        dir.readiness
+            .to_owned()
+            .unwrap_or_else(|| self.get_readiness())
+    }
+    ///Readiness of watched folder orders: companion file and/or folder strategy;
+    /// Loose files without either are ready once unchanged for 2 scans
+    pub fn get_watch_dir_readiness(&self, dir: &WatchDir) -> ReadinessConfig {
+        let companion = dir
+            .companion
+            .as_ref()
+            .map(|suffix| ReadinessConfig::Companion {
+                suffix: suffix.to_owned(),
+            });
+        match (companion, dir.readiness.to_owned()) {
+            (Some(companion), Some(readiness)) => ReadinessConfig::All {
+                of: vec![companion, readiness],
+            },
+            (Some(companion), None) => companion,
+            (None, Some(readiness)) => readiness,
+            (None, None) if dir.file_orders => ReadinessConfig::Stable { scans: 2 },
+            (None, None) => self.get_readiness(),
+        }
+    }
+    pub fn get_upload_flag(&self, dir: &UploadDir) -> NamePattern {
+        dir.flag.to_owned().unwrap_or_else(|| {
+            NamePattern::new(&format!("*.{}", self.ready_flag_file_ext))
+                .expect("flag extension is a valid glob")
+        })
+    }
+    ///Local orders uploaded in keep mode, kept next to the download state file
+    pub fn get_upload_state_file_path(&self) -> PathBuf {
+        self.get_state_file_path()
+            .with_file_name(".ftp_sync_upload_state.json")
+    }
+    ///Uploads out of attempts, so a restart does not send them again
+    pub fn get_upload_dead_letters_file_path(&self) -> PathBuf {
+        self.get_state_file_path()
+            .with_file_name(".ftp_sync_upload_dead_letters.json")
+    }
+    pub fn get_journal_file_path(&self) -> PathBuf {
+        match &self.journal_file {
+            Some(path) => PathBuf::from(path),
+            None => PathBuf::from(&self.local_folder).join(".ftp_sync_journal.jsonl"),
+        }
+    }
+    ///Watched folder with the provided path, None if it is not configured anymore
+    pub fn get_watch_dir(&self, path: &str) -> Option<WatchDir> {
+        self.dirs_to_watch
+            .iter()
+            .find(|dir| dir.path == path)
+            .cloned()
+    }
+    pub fn get_state_file_path(&self) -> PathBuf {
+        match &self.state_file {
+            Some(path) => PathBuf::from(path),
+            None => PathBuf::from(&self.local_folder).join(".ftp_sync_state.json"),
+        }
+    }
+    ///Configured readiness strategy;
+    /// Falls back to "any file with `ready_flag_file_ext` extension"
+    pub fn get_readiness(&self) -> ReadinessConfig {
+        self.readiness
+            .to_owned()
+            .unwrap_or_else(|| ReadinessConfig::FlagExtension {
+                ext: self.ready_flag_file_ext.to_owned(),
+            })
+    }
     pub fn get_healthcheck_interval(&self) -> u16 {
-        self.conn_healthcheck_rate_sec as u16
+        self.conn_healthcheck_rate_sec
+    }
+}
+
+/// Thresholds after which an idle connection is proactively closed and replaced;
+/// Every threshold is optional, unset means "never recycle for this reason"
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RecycleConfig {
+    pub max_age_sec: Option<u64>,
+    pub max_transfers: Option<u64>,
+    pub max_bytes: Option<u64>,
+    pub max_errors: Option<usize>,
+    #[serde(default = "default_error_window_sec")]
+    pub error_window_sec: u64,
+}
+impl Default for RecycleConfig {
+    fn default() -> Self {
+        Self {
+            max_age_sec: None,
+            max_transfers: None,
+            max_bytes: None,
+            max_errors: None,
+            error_window_sec: default_error_window_sec(),
+        }
+    }
+}
+fn default_error_window_sec() -> u64 {
+    300
+}
+
+/// Reconnect schedule of single connections and the pool-wide circuit breaker
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct ReconnectConfig {
+    pub initial_backoff_ms: u64,
+    pub max_backoff_ms: u64,
+    pub multiplier: f64,
+    ///Relative random spread of every delay, 0.0 - 1.0
+    pub jitter: f64,
+    ///Consecutive failed reconnects (pool-wide) that open the circuit
+    pub breaker_failure_threshold: u32,
+    ///How long the circuit stays open before a probe is allowed
+    pub breaker_open_sec: u64,
+}
+impl Default for ReconnectConfig {
+    fn default() -> Self {
+        Self {
+            initial_backoff_ms: 1000,
+            max_backoff_ms: 300_000,
+            multiplier: 2.0,
+            jitter: 0.2,
+            breaker_failure_threshold: 10,
+            breaker_open_sec: 60,
+        }
+    }
+}
+
+/// How the controller checks that an idle connection is usable
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(tag = "mode", rename_all = "lowercase")]
+pub enum HealthCheck {
+    ///NOOP over the control channel
+    #[default]
+    Noop,
+    ///PWD over the control channel
+    Pwd,
+    ///LIST of the provided path, exercises the data channel
+    List { path: String },
+    ///RETR of a small canary file, exercises the data channel end-to-end
+    Canary { path: String },
+}
+
+/// How failed orders are retried before they are dead-lettered
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct RetryPolicy {
+    ///Attempts including the first one
+    pub max_attempts: u32,
+    pub initial_backoff_sec: u64,
+    pub max_backoff_sec: u64,
+    pub multiplier: f64,
+}
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            initial_backoff_sec: 30,
+            max_backoff_sec: 3600,
+            multiplier: 2.0,
+        }
+    }
+}
+impl RetryPolicy {
+    ///Delay before the next attempt after `attempts` failed ones
+    pub fn delay(&self, attempts: u32) -> std::time::Duration {
+        let exp = self.multiplier.powi(attempts.saturating_sub(1) as i32);
+        let secs = (self.initial_backoff_sec as f64 * exp).min(self.max_backoff_sec as f64);
+        std::time::Duration::from_secs_f64(secs.max(0.0))
+    }
+}
+
+/// Limits of concurrent file transfers
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct ParallelConfig {
+    ///Max connections a single order is downloaded over (idle transfer connections only)
+    pub connections_per_order: usize,
+    ///Max files transferred at once over all orders, 0 - limited by pool size only
+    pub max_transfers: usize,
+}
+impl Default for ParallelConfig {
+    fn default() -> Self {
+        Self {
+            connections_per_order: 1,
+            max_transfers: 0,
+        }
     }
 }
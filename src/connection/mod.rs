@@ -1,16 +1,125 @@
-use crate::{config::Config, utils, CustomError, FtpDirEntry};
+use crate::{
+    config::Config, logger, metrics, utils, AuditAction, AuditLog, AuditRecord, Backoff,
+    CustomError, FtpDirEntry, HealthCheck, ListedEntry,
+};
+use async_ftp::status;
 use async_ftp::types::FileType;
 use async_ftp::FtpStream;
+use sha2::{Digest, Sha256};
+use std::collections::{HashSet, VecDeque};
 use std::fmt;
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use std::path::PathBuf;
-use tokio::io::AsyncWriteExt;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tracing::{debug, field::Empty, info_span, warn, Instrument, Span};
+
+///Bytes read from a data connection at once
+const CHUNK_SIZE: usize = 64 * 1024;
+
+///Ids of connections, unique within the process
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
 
 pub type Result<T> = std::result::Result<T, CustomError>;
 
 pub struct Connection {
+    id: u64,
     stream: async_ftp::FtpStream,
     config: Config,
+    stats: ConnectionStats,
+    backoff: Backoff,
+    activity: Arc<Activity>,
+    health: HealthHistory,
+    audit: Option<Arc<AuditLog>>,
+}
+
+/// Usage counters of a single ftp session, reset every time the session is re-established
+#[derive(Debug, Clone)]
+pub struct ConnectionStats {
+    pub connected_at: Instant,
+    pub transfers: u64,
+    pub bytes: u64,
+    errors: VecDeque<Instant>,
+}
+impl Default for ConnectionStats {
+    fn default() -> Self {
+        Self {
+            connected_at: Instant::now(),
+            transfers: 0,
+            bytes: 0,
+            errors: VecDeque::new(),
+        }
+    }
+}
+/// Results of the latest health checks of a pool slot, kept across reconnects
+#[derive(Debug, Clone, Default)]
+pub struct HealthHistory {
+    checks: VecDeque<(Instant, bool)>,
+}
+impl HealthHistory {
+    const CAPACITY: usize = 20;
+
+    pub fn record(&mut self, healthy: bool) {
+        if self.checks.len() == Self::CAPACITY {
+            self.checks.pop_front();
+        }
+        self.checks.push_back((Instant::now(), healthy));
+    }
+    ///Share of successful checks, 1.0 if there were none yet
+    pub fn score(&self) -> f64 {
+        if self.checks.is_empty() {
+            return 1.0;
+        }
+        let passed = self.checks.iter().filter(|(_, ok)| *ok).count();
+        passed as f64 / self.checks.len() as f64
+    }
+    pub fn last(&self) -> Option<(Instant, bool)> {
+        self.checks.back().copied()
+    }
+    pub fn iter(&self) -> impl Iterator<Item = &(Instant, bool)> {
+        self.checks.iter()
+    }
+}
+
+/// State of a connection shared with the pool, so it can be reported while the connection is busy
+#[derive(Debug, Default)]
+pub struct Activity {
+    degraded: AtomicBool,
+    ///Last command started, describes what a busy connection is doing
+    current: std::sync::Mutex<Option<String>>,
+}
+impl Activity {
+    pub fn is_degraded(&self) -> bool {
+        self.degraded.load(Ordering::Relaxed)
+    }
+    pub fn current(&self) -> Option<String> {
+        self.current
+            .lock()
+            .expect("activity lock poisoned")
+            .to_owned()
+    }
+}
+
+impl ConnectionStats {
+    pub fn record_transfer(&mut self, bytes: u64) {
+        self.transfers += 1;
+        self.bytes += bytes;
+    }
+    pub fn record_error(&mut self) {
+        self.errors.push_back(Instant::now());
+    }
+    ///Number of errors recorded during the last `window`
+    pub fn recent_errors(&mut self, window: Duration) -> usize {
+        while let Some(at) = self.errors.front() {
+            if at.elapsed() <= window {
+                break;
+            }
+            self.errors.pop_front();
+        }
+        self.errors.len()
+    }
 }
 impl fmt::Debug for Connection {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -36,36 +145,216 @@ impl Connection {
         //connect
         //loop trying to connec
         let host = conf.get_hostname();
-        println!("connecting to {:?}", &host);
+        debug!(host = %host, "connecting");
         let mut stream = FtpStream::connect(host)
             .await
-            .map_err(|e| tokio::io::Error::new(std::io::ErrorKind::Other, e.to_string()))?;
+            .map_err(|e| tokio::io::Error::other(e.to_string()))?;
         stream
             .login(&conf.user, &conf.pass)
             .await
-            .map_err(|e| tokio::io::Error::new(std::io::ErrorKind::Other, e.to_string()))?;
+            .map_err(CustomError::from_login)?;
         stream.transfer_type(FileType::Binary).await?;
         Ok(Self {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
             config: conf.to_owned(),
             stream,
+            stats: ConnectionStats::default(),
+            backoff: Backoff::default(),
+            activity: Arc::new(Activity::default()),
+            health: HealthHistory::default(),
+            audit: None,
         })
     }
+    ///Re-establish the session;
+    /// On failure the connection stays degraded and its next attempt is scheduled with backoff
     pub async fn restore(&mut self) -> Result<()> {
+        self.doing("reconnecting".into());
+        let span = info_span!(
+            "restore",
+            host = %self.config.get_hostname(),
+            error = Empty,
+            reply_code = Empty,
+            otel.status_code = Empty
+        );
+        let res = self.reconnect().instrument(span.clone()).await;
+        metrics::reconnect(&self.config.get_profile(), res.is_ok());
+        if let Err(e) = &res {
+            logger::record_error(&span, e);
+        }
+        if res.is_ok() {
+            self.stats = ConnectionStats::default();
+            self.backoff.reset();
+            self.set_degraded(false);
+        } else {
+            self.backoff.fail(&self.config.reconnect);
+            self.set_degraded(true);
+        }
+        res
+    }
+    async fn reconnect(&mut self) -> Result<()> {
         let host = self.config.get_hostname();
-        println!("connecting to {:?}", &host);
+        debug!(host = %host, "reconnecting");
         self.stream = FtpStream::connect(host).await?;
         self.stream
             .login(&self.config.user, &self.config.pass)
-            .await?;
+            .await
+            .map_err(CustomError::from_login)?;
         self.stream.transfer_type(FileType::Binary).await?;
         Ok(())
     }
+    pub fn get_config(&self) -> &Config {
+        &self.config
+    }
+    pub fn get_id(&self) -> u64 {
+        self.id
+    }
+    ///Span everything done over this connection is recorded in
+    pub fn span(&self) -> Span {
+        info_span!("connection", id = self.id, host = %self.config.host)
+    }
+    ///Replace config (credentials) used by the next reconnect
+    pub fn set_config(&mut self, config: Config) {
+        self.config = config;
+    }
+    ///Record received and sent files and remote deletes to the audit trail
+    pub fn set_audit(&mut self, audit: Option<Arc<AuditLog>>) {
+        self.audit = audit;
+    }
+    pub fn get_stats(&self) -> &ConnectionStats {
+        &self.stats
+    }
+    pub fn get_backoff(&self) -> &Backoff {
+        &self.backoff
+    }
+    ///Connection failed its health check and was not restored yet
+    pub fn is_degraded(&self) -> bool {
+        self.activity.is_degraded()
+    }
+    pub fn set_degraded(&mut self, degraded: bool) {
+        self.activity.degraded.store(degraded, Ordering::Relaxed);
+    }
+    ///Shared view of state and current command, readable while the connection is checked out
+    pub fn get_activity(&self) -> Arc<Activity> {
+        self.activity.clone()
+    }
+    fn doing(&self, what: String) {
+        *self
+            .activity
+            .current
+            .lock()
+            .expect("activity lock poisoned") = Some(what);
+    }
+    pub fn get_health(&self) -> &HealthHistory {
+        &self.health
+    }
+    ///Score used by the pool to prefer healthy connections, degraded ones come last
+    pub fn health_score(&self) -> f64 {
+        if self.is_degraded() {
+            return -1.0;
+        }
+        self.health.score()
+    }
+    ///Run configured health check and record its result
+    pub async fn health_check(&mut self) -> Result<()> {
+        self.doing("health check".into());
+        let res = match self.config.healthcheck.to_owned() {
+            HealthCheck::Noop => self.noop().await,
+            HealthCheck::Pwd => self.pwd().await.map(|_| ()),
+            HealthCheck::List { path } => self.list(Some(&path)).await.map(|_| ()),
+            HealthCheck::Canary { path } => self.simple_retr(&path).await.map(|_| ()),
+        };
+        self.health.record(res.is_ok());
+        if res.is_err() {
+            self.stats.record_error();
+        }
+        res.map_err(|e| e.into())
+    }
+    ///Returns the reason this connection should be replaced, if any of the configured
+    /// recycle thresholds (age, transfers, bytes, recent errors) was exceeded
+    pub fn recycle_reason(&mut self) -> Option<String> {
+        let limits = self.config.recycle.to_owned();
+        let age = self.stats.connected_at.elapsed();
+        if limits.max_age_sec.is_some_and(|max| age.as_secs() >= max) {
+            return Some(format!("age {}s", age.as_secs()));
+        }
+        if limits
+            .max_transfers
+            .is_some_and(|max| self.stats.transfers >= max)
+        {
+            return Some(format!("{} transfers", self.stats.transfers));
+        }
+        if limits.max_bytes.is_some_and(|max| self.stats.bytes >= max) {
+            return Some(format!("{} bytes transferred", self.stats.bytes));
+        }
+        let window = Duration::from_secs(limits.error_window_sec);
+        let errors = self.stats.recent_errors(window);
+        if limits.max_errors.is_some_and(|max| errors >= max) {
+            return Some(format!("{} errors in {}s", errors, limits.error_window_sec));
+        }
+        None
+    }
+    async fn audit(&self, action: AuditAction, path: &str, fill: impl FnOnce(&mut AuditRecord)) {
+        if let Some(audit) = &self.audit {
+            let mut record =
+                AuditRecord::new(action, &self.config.get_hostname(), &self.config.user, path);
+            fill(&mut record);
+            audit.record(record).await;
+        }
+    }
+    ///Gracefully close the session (QUIT); connection stays degraded until restored
+    pub async fn close(&mut self) {
+        self.doing("closing".into());
+        self.quit().await.ok();
+        self.set_degraded(true);
+    }
     pub async fn batch_delete_remote(&mut self, entries: &[FtpDirEntry]) -> Result<()> {
+        self.batch_delete_remote_with(entries, |_| {}).await
+    }
+    ///Same as `batch_delete_remote`, calling `on_deleted` after every removed entry
+    pub async fn batch_delete_remote_with(
+        &mut self,
+        entries: &[FtpDirEntry],
+        mut on_deleted: impl FnMut(&FtpDirEntry) + Send,
+    ) -> Result<()> {
         for entry in entries.iter().rev() {
-            match entry {
-                FtpDirEntry::File(p, ..) => self.rm(p).await?,
-                FtpDirEntry::Folder(p) => self.rmdir(p).await?,
+            self.doing(format!("DELE {}", entry.get_full_path()));
+            let span = info_span!(
+                "delete",
+                command = Empty,
+                path = %entry.get_full_path(),
+                size = Empty,
+                error = Empty,
+                reply_code = Empty,
+                otel.status_code = Empty
+            );
+            let started = Instant::now();
+            let (command, res) = match entry {
+                FtpDirEntry::File(p, size) => {
+                    span.record("size", *size as i64);
+                    ("DELE", self.rm(p).instrument(span.clone()).await)
+                }
+                FtpDirEntry::Folder(p) => ("RMD", self.rmdir(p).instrument(span.clone()).await),
             };
+            span.record("command", command);
+            metrics::command(&self.config.get_profile(), command, started.elapsed());
+            self.audit(
+                AuditAction::RemoteDeleted,
+                &entry.get_full_path(),
+                |record| {
+                    if let FtpDirEntry::File(_, size) = entry {
+                        record.size = Some(*size);
+                    }
+                    record.error = res.as_ref().err().map(|e| e.to_string());
+                },
+            )
+            .await;
+            if let Err(e) = res {
+                self.stats.record_error();
+                let e = e.into();
+                logger::record_error(&span, &e);
+                return Err(e);
+            }
+            on_deleted(entry);
         }
         Ok(())
     }
@@ -74,44 +363,22 @@ impl Connection {
         &mut self,
         files: Vec<FtpDirEntry>,
         dest: &str,
+    ) -> Result<Vec<FtpDirEntry>> {
+        self.batch_download_with(files, dest, |_| {}).await
+    }
+    ///Same as `batch_download`, calling `on_downloaded` after every file present locally
+    pub async fn batch_download_with(
+        &mut self,
+        files: Vec<FtpDirEntry>,
+        dest: &str,
+        mut on_downloaded: impl FnMut(&FtpDirEntry) + Send,
     ) -> Result<Vec<FtpDirEntry>> {
         let mut failed_files: Vec<FtpDirEntry> = Vec::with_capacity(files.len());
         for file in files.iter() {
-            let (path, &size) = match &file {
-                FtpDirEntry::File(p, s) => (p.to_owned(), s),
-                FtpDirEntry::Folder(..) => continue,
-            };
-            let ftp_root: String = path
-                .to_owned()
-                .chars()
-                .take_while(|ch| *ch != '/')
-                .chain("/".chars())
-                .collect();
-            let target_file_path = utils::get_download_target_path(&path, dest, &ftp_root);
-
-            if let Ok(file) = tokio::fs::File::open(&target_file_path).await {
-                //file already exists
-                if let Ok(meta) = file.metadata().await {
-                    if meta.len() == size as u64 {
-                        //and it was fully downloaded
-                        continue;
-                    }
-                }
-            } else {
-                match self.download_file(&path, &target_file_path).await {
-                    Ok(()) => {
-                        println!("downloaded file from {} to {}", path, &target_file_path);
-                        continue;
-                    }
-                    Err(e) => {
-                        println!("failed to download file to {}", &target_file_path);
-                        println!("error: {:?}", e);
-                        failed_files.push(file.clone());
-                    }
-                }
-                //file does not exist
-                //try to download
-                //if failed => write to failed_files and continue to next iteration
+            //if failed => write to failed_files and continue to next iteration
+            match self.fetch_file(file, dest).await {
+                Ok(()) => on_downloaded(file),
+                Err(_) => failed_files.push(file.clone()),
             }
         }
         if !failed_files.is_empty() {
@@ -123,11 +390,47 @@ impl Connection {
         Ok(files)
     }
 
+    ///List folder keeping modification dates reported by the server
+    pub async fn get_dir_listing(&mut self, path: &str) -> Result<Vec<ListedEntry>> {
+        self.doing(format!("LIST {}", path));
+        let started = Instant::now();
+        let entries = self.traced_list(path).await;
+        metrics::command(&self.config.get_profile(), "LIST", started.elapsed());
+        let entries = entries.inspect_err(|_| self.stats.record_error())?;
+        Ok(utils::parse_ftp_listing(entries, path))
+    }
     pub async fn get_dir_entries(&mut self, path: &str) -> Result<Vec<FtpDirEntry>> {
-        let entries = self.list(Some(path)).await?;
+        self.doing(format!("LIST {}", path));
+        let started = Instant::now();
+        let entries = self.traced_list(path).await;
+        metrics::command(&self.config.get_profile(), "LIST", started.elapsed());
+        let entries = entries.inspect_err(|_| self.stats.record_error())?;
         let result: Vec<FtpDirEntry> = utils::parse_ftp_entries(entries, path);
         Ok(result)
     }
+    ///LIST inside its own span
+    async fn traced_list(&mut self, path: &str) -> Result<Vec<String>> {
+        let span = info_span!(
+            "list",
+            path,
+            entries = Empty,
+            error = Empty,
+            reply_code = Empty,
+            otel.status_code = Empty
+        );
+        let res = self
+            .list(Some(path))
+            .instrument(span.clone())
+            .await
+            .map_err(CustomError::from);
+        match &res {
+            Ok(entries) => {
+                span.record("entries", entries.len() as i64);
+            }
+            Err(e) => logger::record_error(&span, e),
+        }
+        res
+    }
     //get_size returns size of entry at provided path.
     //none if no such entry, -1 if entry is dir
     //assuming no symlinks on ftp server
@@ -141,17 +444,220 @@ impl Connection {
             None
         }
     }
-    pub async fn download_file(&mut self, path: &str, to: &str) -> Result<()> {
+    ///Download single file of an order into `dest` folder;
+    /// File already present locally with the same size is not downloaded again, folders are skipped
+    pub async fn fetch_file(&mut self, file: &FtpDirEntry, dest: &str) -> Result<()> {
+        self.fetch_file_with(file, None, dest, |_| {}).await
+    }
+    ///Same as `fetch_file`, calling `on_progress` with the number of bytes of every received chunk;
+    /// With the listing date `modified` (unix seconds) the local file must have it too to be skipped,
+    /// it is given to every downloaded file
+    pub async fn fetch_file_with(
+        &mut self,
+        file: &FtpDirEntry,
+        modified: Option<i64>,
+        dest: &str,
+        on_progress: impl FnMut(u64) + Send,
+    ) -> Result<()> {
+        let (path, size) = match file {
+            FtpDirEntry::File(p, s) => (p, *s),
+            FtpDirEntry::Folder(..) => return Ok(()),
+        };
+        let target_file_path = utils::get_local_path(path, dest);
+        if let Ok(meta) = tokio::fs::metadata(&target_file_path).await {
+            //file already exists, was fully downloaded and was not changed remotely since
+            let same_date = modified.is_none_or(|m| utils::modified_secs(&meta) == Some(m));
+            if meta.len() == size as u64 && same_date {
+                return Ok(());
+            }
+        }
+        //file does not exist or is partial/outdated
+        self.doing(format!("RETR {}", path));
+        let span = info_span!(
+            "download",
+            path = %path,
+            local_path = %target_file_path,
+            size = size as i64,
+            error = Empty,
+            reply_code = Empty,
+            otel.status_code = Empty
+        );
+        let started = Instant::now();
+        let res = self
+            .download_file_with(path, &target_file_path, on_progress)
+            .instrument(span.clone())
+            .await;
+        if let Err(e) = &res {
+            logger::record_error(&span, e);
+        }
+        drop(span);
+        metrics::command(&self.config.get_profile(), "RETR", started.elapsed());
+        self.audit(AuditAction::Received, path, |record| {
+            record.local_path = Some(target_file_path.to_owned());
+            record.size = Some(size);
+            record.duration_ms = Some(started.elapsed().as_millis() as u64);
+            match &res {
+                Ok(checksum) => record.checksum = Some(checksum.to_owned()),
+                Err(e) => record.error = Some(format!("{:?}", e)),
+            }
+        })
+        .await;
+        match res {
+            Ok(_) => {
+                self.stats.record_transfer(size as u64);
+                debug!(from = %path, to = %target_file_path, size, "downloaded file");
+                //a later fetch skips it only while the remote file keeps this date
+                if let Some(modified) = modified {
+                    if let Err(e) = utils::set_modified_secs(&target_file_path, modified).await {
+                        warn!(path = %target_file_path, error = ?e, "failed to set modification time");
+                    }
+                }
+                Ok(())
+            }
+            Err(e) => {
+                self.stats.record_error();
+                warn!(from = %path, to = %target_file_path, error = ?e, "failed to download file");
+                Err(e)
+            }
+        }
+    }
+    ///Download remote file to local path; returns sha256 of its content
+    pub async fn download_file(&mut self, path: &str, to: &str) -> Result<String> {
+        self.download_file_with(path, to, |_| {}).await
+    }
+    ///Same as `download_file`, streaming to disk and calling `on_progress` with the size of every chunk
+    pub async fn download_file_with(
+        &mut self,
+        path: &str,
+        to: &str,
+        mut on_progress: impl FnMut(u64) + Send,
+    ) -> Result<String> {
         //check if destination folder path exists
         let mut dest = PathBuf::from(&to);
         dest.pop();
         tokio::fs::create_dir_all(&dest).await?;
         let mut file = tokio::fs::File::create(&to).await?;
 
-        let cursor = self.simple_retr(path).await?;
-        file.write_all(&cursor.into_inner()).await?;
+        let mut stream = self.get(path).await?;
+        let mut hasher = Sha256::new();
+        let mut buf = vec![0u8; CHUNK_SIZE];
+        loop {
+            let read = stream.read(&mut buf).await?;
+            if read == 0 {
+                break;
+            }
+            hasher.update(&buf[..read]);
+            file.write_all(&buf[..read]).await?;
+            on_progress(read as u64);
+        }
+        file.flush().await?;
+        //data connection must be closed before the server confirms the transfer
+        drop(stream);
+        self.read_response_in(&[
+            status::CLOSING_DATA_CONNECTION,
+            status::REQUESTED_FILE_ACTION_OK,
+        ])
+        .await?;
+        Ok(hasher
+            .finalize()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect())
+    }
+    ///Move remote folder into the archive folder, creating it if needed;
+    /// Existing archived folder of the same name is not overwritten, timestamp is appended instead
+    pub async fn archive_remote(&mut self, path: &str, archive_dir: &str) -> Result<String> {
+        self.doing(format!("RNFR {}", path));
+        //archive folder usually exists already
+        self.mkdir(archive_dir).await.ok();
+        let name = utils::get_file_name(path);
+        let target = format!("{}/{}", archive_dir, name);
+        if self.rename(path, &target).await.is_ok() {
+            return Ok(target);
+        }
+        let secs = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        let target = format!("{}/{}_{}", archive_dir, name, secs);
+        self.rename(path, &target)
+            .await
+            .inspect_err(|_| self.stats.record_error())?;
+        Ok(target)
+    }
+    ///Upload (local, remote) file pairs in the provided order, creating remote folders as needed
+    pub async fn batch_upload(&mut self, files: &[(String, String)]) -> Result<()> {
+        self.batch_upload_with(files, |_, _| {}).await
+    }
+    ///Same as `batch_upload`, calling `on_uploaded` with remote path and size after every stored file
+    pub async fn batch_upload_with(
+        &mut self,
+        files: &[(String, String)],
+        mut on_uploaded: impl FnMut(&str, u64) + Send,
+    ) -> Result<()> {
+        let mut created: HashSet<String> = HashSet::new();
+        for (local, remote) in files.iter() {
+            if let Some((dir, _)) = remote.rsplit_once('/') {
+                if !created.contains(dir) {
+                    self.create_remote_dir_all(dir).await;
+                    created.insert(dir.to_owned());
+                }
+            }
+            match self.upload_file(local, remote).await {
+                Ok(size) => {
+                    self.stats.record_transfer(size);
+                    debug!(from = %local, to = %remote, "uploaded file");
+                    on_uploaded(remote, size);
+                }
+                Err(e) => {
+                    self.stats.record_error();
+                    warn!(from = %local, to = %remote, error = ?e, "failed to upload file");
+                    return Err(e);
+                }
+            }
+        }
         Ok(())
     }
+    ///STOR to a temporary name in the target folder, then rename,
+    /// so the partner never sees a partially written file
+    pub async fn upload_file(&mut self, from: &str, to: &str) -> Result<u64> {
+        self.doing(format!("STOR {}", to));
+        let started = Instant::now();
+        let res = self.store_renamed(from, to).await;
+        metrics::command(&self.config.get_profile(), "STOR", started.elapsed());
+        self.audit(AuditAction::Sent, to, |record| {
+            record.local_path = Some(from.to_owned());
+            record.duration_ms = Some(started.elapsed().as_millis() as u64);
+            match &res {
+                Ok(size) => record.size = Some(*size as usize),
+                Err(e) => record.error = Some(format!("{:?}", e)),
+            }
+        })
+        .await;
+        res
+    }
+    async fn store_renamed(&mut self, from: &str, to: &str) -> Result<u64> {
+        let mut file = tokio::fs::File::open(from).await?;
+        let size = file.metadata().await?.len();
+        let tmp = match to.rsplit_once('/') {
+            Some((dir, name)) => format!("{}/.{}.part", dir, name),
+            None => format!(".{}.part", to),
+        };
+        self.put(&tmp, &mut file).await?;
+        self.rename(&tmp, to).await?;
+        Ok(size)
+    }
+    ///MKD every missing folder of the path; existing folders make MKD fail, which is ignored
+    pub async fn create_remote_dir_all(&mut self, path: &str) {
+        let mut current = String::new();
+        for segment in path.split('/').filter(|s| !s.is_empty()) {
+            if !current.is_empty() || path.starts_with('/') {
+                current.push('/');
+            }
+            current.push_str(segment);
+            self.mkdir(&current).await.ok();
+        }
+    }
     pub async fn remove_dir(&mut self, path: &str) -> Result<()> {
         self.rmdir(path).await.map_err(|e| e.into())
     }
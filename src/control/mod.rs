@@ -0,0 +1,56 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+
+/// Work of the engine that can be paused
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Work {
+    ///Looking for new orders in watched folders
+    Scanning,
+    ///Starting downloads of queued orders; running ones are not affected
+    Downloading,
+}
+
+/// What is paused, globally or per watched folder
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct Paused {
+    pub scanning: bool,
+    pub downloading: bool,
+    pub scan_dirs: BTreeSet<String>,
+    pub download_dirs: BTreeSet<String>,
+}
+
+impl Paused {
+    ///Pause or resume work everywhere (`watch_dir` is None) or in a single watched folder;
+    /// Resuming globally doesn't resume folders paused one by one
+    pub fn set(&mut self, work: Work, watch_dir: Option<&str>, paused: bool) {
+        let (global, dirs) = match work {
+            Work::Scanning => (&mut self.scanning, &mut self.scan_dirs),
+            Work::Downloading => (&mut self.downloading, &mut self.download_dirs),
+        };
+        match watch_dir {
+            None => *global = paused,
+            Some(dir) if paused => {
+                dirs.insert(dir.to_owned());
+            }
+            Some(dir) => {
+                dirs.remove(dir);
+            }
+        }
+    }
+    ///Whether work is paused globally
+    pub fn is_paused(&self, work: Work) -> bool {
+        match work {
+            Work::Scanning => self.scanning,
+            Work::Downloading => self.downloading,
+        }
+    }
+    ///Whether work is paused for the watched folder, globally or on its own
+    pub fn is_paused_in(&self, work: Work, watch_dir: &str) -> bool {
+        self.is_paused(work)
+            || match work {
+                Work::Scanning => self.scan_dirs.contains(watch_dir),
+                Work::Downloading => self.download_dirs.contains(watch_dir),
+            }
+    }
+}
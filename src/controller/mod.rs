@@ -1,13 +1,19 @@
-use crate::Pool;
+use crate::{CircuitState, Connection, CustomError, Event, EventStream, Pool};
 use std::sync::Arc;
 use tokio::{
     spawn,
+    sync::broadcast,
     //task::JoinHandle,
-    time::{interval, Duration},
+    time::{interval, Duration, Instant},
 };
+use tracing::{debug, info, warn, Instrument};
+
+/// How often degraded connections are looked at for a due reconnect attempt
+const RESTORE_POLL_INTERVAL: Duration = Duration::from_secs(1);
 
 /// Controller spawns a task that periodically polls each connection to keep it alive
 /// If connection is closed => replace it with newly created one
+/// Reconnects follow per-connection exponential backoff and the pool-wide circuit breaker
 #[derive(Debug)]
 pub struct Controller {
     connections: Arc<Pool>,
@@ -16,32 +22,112 @@ impl Controller {
     pub fn new(pool: Arc<Pool>) -> Self {
         Self { connections: pool }
     }
+    ///Subscribe to engine events (shared with the pool and watcher)
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.connections.subscribe()
+    }
+    ///Engine events as a stream, missed events are reported as `Event::Lagged`
+    pub fn events(&self) -> EventStream {
+        self.connections.events()
+    }
     pub fn start(&'static mut self, healthcheck_interval_sec: u16) -> impl std::future::Future {
         spawn(async move {
-            let mut interval = interval(Duration::from_secs(healthcheck_interval_sec as u64));
+            let healthcheck_every = Duration::from_secs(healthcheck_interval_sec as u64);
+            let mut last_healthcheck: Option<Instant> = None;
+            let mut interval = interval(RESTORE_POLL_INTERVAL);
             loop {
                 interval.tick().await;
+                let healthcheck_due =
+                    last_healthcheck.is_none_or(|at| at.elapsed() >= healthcheck_every);
+                if healthcheck_due {
+                    last_healthcheck = Some(Instant::now());
+                }
 
                 // loop through connections
                 // if can't get the lock => connection is busy
-                // if lock acquired => check conn status:
+                // if degraded => try to restore once its backoff delay is over
+                // if lock acquired and healthcheck is due => check conn status:
+                // if worn out (age, transfers, bytes, errors) => recycle it while it is idle
                 // if bad => try to restore
                 // if failed to restore => continue
-                for ftp_conn in self.connections.get_connections().iter() {
-                    println!("checking connection..");
+                for (index, ftp_conn) in self.connections.get_connections().enumerate() {
                     if let Ok(mut conn) = ftp_conn.try_lock() {
-                        println!("acquired conn mutex. Checking ftp response..");
-                        if conn.noop().await.is_err() {
-                            println!("connection degraded, restoring");
-                            conn.restore().await.ok();
-                        } else {
-                            println!("connection is Ok!");
-                        }
-                    } else {
-                        println!("connection is busy");
+                        let span = conn.span();
+                        self.maintain(&mut conn, healthcheck_due)
+                            .instrument(span)
+                            .await;
+                    } else if healthcheck_due {
+                        debug!(connection = index, "connection is busy");
                     }
                 }
             }
         })
     }
+    ///Restore degraded connection; when health check is due, recycle worn out or check idle connection
+    async fn maintain(&self, conn: &mut Connection, healthcheck_due: bool) {
+        if conn.is_degraded() {
+            self.try_restore(conn).await;
+            return;
+        }
+        if !healthcheck_due {
+            return;
+        }
+        //never drop a working session when it can't be re-established
+        let can_recycle = self.connections.circuit_state() == CircuitState::Closed
+            && self.connections.get_auth_failure().is_none();
+        if can_recycle {
+            if let Some(reason) = conn.recycle_reason() {
+                info!(reason = %reason, "recycling connection");
+                conn.close().await;
+                self.try_restore(conn).await;
+                return;
+            }
+        }
+        debug!("checking connection health");
+        if let Err(e) = conn.health_check().await {
+            warn!(error = ?e, "connection degraded, restoring");
+            self.emit_down(conn, &e);
+            conn.set_degraded(true);
+            self.try_restore(conn).await;
+        } else {
+            debug!("connection is Ok");
+        }
+    }
+    ///Reconnect degraded connection unless its backoff delay is running, the circuit is open
+    /// or the server rejected current credentials
+    async fn try_restore(&self, conn: &mut Connection) {
+        if !conn.get_backoff().is_due() || !self.connections.allows_reconnect() {
+            return;
+        }
+        //pick up credentials changed by config reload
+        conn.set_config(self.connections.get_config());
+        match conn.restore().await {
+            Ok(()) => {
+                info!("connection restored");
+                self.connections.emit(Event::ConnectionUp {
+                    connection: conn.get_id(),
+                });
+                self.connections.record_reconnect(true);
+            }
+            Err(CustomError::Auth(message)) => {
+                self.connections.record_auth_failure(message);
+            }
+            Err(e) => {
+                warn!(
+                    attempt = conn.get_backoff().failures(),
+                    error = ?e,
+                    "failed to restore connection"
+                );
+                self.emit_down(conn, &e);
+                self.connections.record_reconnect(false);
+            }
+        }
+    }
+    fn emit_down(&self, conn: &Connection, e: &CustomError) {
+        self.connections.emit(Event::ConnectionDown {
+            connection: conn.get_id(),
+            kind: e.kind(),
+            message: format!("{:?}", e),
+        });
+    }
 }
@@ -1,3 +1,4 @@
+use crate::events::ErrorKind;
 use async_ftp::FtpError;
 use serde_json::Error as SerdeError;
 use std::io::Error;
@@ -7,6 +8,48 @@ pub enum CustomError {
     Io(String),
     Ftp(String),
     Serde(String),
+    ///Server rejected credentials (reply 530 or 332); retrying will not help
+    Auth(String),
+}
+
+impl CustomError {
+    ///Classify error returned by USER/PASS exchange
+    pub fn from_login(src: FtpError) -> Self {
+        match reply_code(&src) {
+            Some(530) | Some(332) => Self::Auth(src.to_string()),
+            _ => src.into(),
+        }
+    }
+    pub fn is_auth(&self) -> bool {
+        matches!(self, Self::Auth(..))
+    }
+    ///Reply code of the unexpected server response this error was made of
+    pub fn reply_code(&self) -> Option<u32> {
+        match self {
+            Self::Ftp(msg) | Self::Auth(msg) => parse_reply_code(msg),
+            _ => None,
+        }
+    }
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Self::Io(..) => ErrorKind::Io,
+            Self::Ftp(..) => ErrorKind::Ftp,
+            Self::Serde(..) => ErrorKind::Data,
+            Self::Auth(..) => ErrorKind::Auth,
+        }
+    }
+}
+
+///Extract reply code of unexpected server response
+pub fn reply_code(src: &FtpError) -> Option<u32> {
+    match src {
+        FtpError::InvalidResponse(msg) => parse_reply_code(msg),
+        _ => None,
+    }
+}
+fn parse_reply_code(msg: &str) -> Option<u32> {
+    let (_, response) = msg.split_once("got response: ")?;
+    response.get(0..3)?.parse().ok()
 }
 
 impl From<Error> for CustomError {
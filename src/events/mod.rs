@@ -0,0 +1,108 @@
+use crate::breaker::CircuitState;
+use crate::{CustomError, OrderState, Progress};
+use futures::{Stream, StreamExt};
+use serde::Serialize;
+use std::pin::Pin;
+use tokio::sync::broadcast;
+use tokio_stream::wrappers::{errors::BroadcastStreamRecvError, BroadcastStream};
+
+/// Error class of `CustomError`, carried by events
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorKind {
+    ///Local file system or network
+    Io,
+    ///Unexpected ftp reply
+    Ftp,
+    ///Malformed data: config, journal, manifest
+    Data,
+    Auth,
+    ///Order hook failed
+    Hook,
+}
+
+/// Events emitted by the engine to its subscribers
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    CircuitStateChanged {
+        from: CircuitState,
+        to: CircuitState,
+    },
+    ///Server rejected credentials of the profile; reconnects are stopped
+    AuthenticationFailed {
+        host: String,
+        user: String,
+        message: String,
+    },
+    ///Profile credentials were replaced via config reload
+    CredentialsChanged {
+        host: String,
+        user: String,
+    },
+    ///Connection (id, as in `ConnectionStatus`) was restored
+    ConnectionUp {
+        connection: u64,
+    },
+    ///Connection failed its health check or a reconnect attempt
+    ConnectionDown {
+        connection: u64,
+        kind: ErrorKind,
+        message: String,
+    },
+    ScanCompleted {
+        watch_dir: String,
+        orders_found: usize,
+        duration_ms: u64,
+    },
+    OrderStateChanged {
+        order: String,
+        state: OrderState,
+    },
+    ///Ready order has the content of an order delivered already; it is not downloaded again and stays on the server
+    OrderAlreadyDelivered {
+        order: String,
+    },
+    ///Reported every `progress_interval_ms` while an order downloads, and once when it is done
+    Progress(Progress),
+    FileDownloaded {
+        order: String,
+        path: String,
+        size: usize,
+    },
+    ///File of an upload order was stored on the server
+    FileUploaded {
+        order: String,
+        path: String,
+        size: usize,
+    },
+    Error {
+        kind: ErrorKind,
+        message: String,
+        ///Order the error happened to, if any
+        order: Option<String>,
+    },
+    ///Subscriber fell behind and missed that many events (stream only)
+    Lagged {
+        missed: u64,
+    },
+}
+impl Event {
+    pub fn error(e: &CustomError, order: Option<&str>) -> Self {
+        Self::Error {
+            kind: e.kind(),
+            message: format!("{:?}", e),
+            order: order.map(|o| o.to_owned()),
+        }
+    }
+}
+
+pub type EventStream = Pin<Box<dyn Stream<Item = Event> + Send>>;
+
+///Turn subscription into a stream; missed events are reported in place as `Event::Lagged`
+pub fn stream(rx: broadcast::Receiver<Event>) -> EventStream {
+    Box::pin(BroadcastStream::new(rx).map(|item| match item {
+        Ok(event) => event,
+        Err(BroadcastStreamRecvError::Lagged(missed)) => Event::Lagged { missed },
+    }))
+}
@@ -0,0 +1,136 @@
+use crate::NamePattern;
+use serde::{Deserialize, Serialize};
+
+/// Which files of an order are downloaded;
+/// Patterns match either the file name or the path relative to the order folder,
+/// a folder matching `exclude` excludes everything inside it
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct EntryFilter {
+    ///If not empty, only matching files are downloaded
+    pub include: Vec<NamePattern>,
+    pub exclude: Vec<NamePattern>,
+    pub min_size: Option<usize>,
+    pub max_size: Option<usize>,
+    ///Skip files and folders whose name starts with a dot
+    pub ignore_hidden: bool,
+}
+
+impl EntryFilter {
+    ///Whether file (full path, `order_path` is the order folder) should be downloaded
+    pub fn accepts(&self, order_path: &str, path: &str, size: usize) -> bool {
+        let relative = path
+            .strip_prefix(order_path)
+            .unwrap_or(path)
+            .trim_start_matches('/');
+        let mut segments: Vec<&str> = relative.split('/').filter(|s| !s.is_empty()).collect();
+        let name = match segments.pop() {
+            Some(name) => name,
+            None => return true,
+        };
+        if self.ignore_hidden
+            && (name.starts_with('.') || segments.iter().any(|s| s.starts_with('.')))
+        {
+            return false;
+        }
+        if self.min_size.is_some_and(|min| size < min)
+            || self.max_size.is_some_and(|max| size > max)
+        {
+            return false;
+        }
+        //every parent folder is checked against exclude list too
+        let mut parent = String::new();
+        for segment in segments.iter() {
+            parent = if parent.is_empty() {
+                segment.to_string()
+            } else {
+                format!("{}/{}", parent, segment)
+            };
+            if self.matches_any(&self.exclude, segment, &parent) {
+                return false;
+            }
+        }
+        if self.matches_any(&self.exclude, name, relative) {
+            return false;
+        }
+        self.include.is_empty() || self.matches_any(&self.include, name, relative)
+    }
+    fn matches_any(&self, patterns: &[NamePattern], name: &str, relative: &str) -> bool {
+        patterns
+            .iter()
+            .any(|p| p.matches(name) || p.matches(relative))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn patterns(src: &[&str]) -> Vec<NamePattern> {
+        src.iter().map(|p| NamePattern::new(p).unwrap()).collect()
+    }
+
+    #[test]
+    fn default_filter_accepts_everything() {
+        let filter = EntryFilter::default();
+        assert!(filter.accepts("/in/o", "/in/o/.hidden/a.tmp", 0));
+    }
+
+    #[test]
+    fn include_matches_name_or_relative_path() {
+        let filter = EntryFilter {
+            include: patterns(&["*.csv", "docs/*.pdf"]),
+            ..Default::default()
+        };
+        assert!(filter.accepts("/in/o", "/in/o/deep/data.csv", 1));
+        assert!(filter.accepts("/in/o", "/in/o/docs/a.pdf", 1));
+        assert!(!filter.accepts("/in/o", "/in/o/other/a.pdf", 1));
+    }
+
+    #[test]
+    fn excluded_folder_excludes_everything_inside() {
+        let filter = EntryFilter {
+            exclude: patterns(&["tmp", "cache/old"]),
+            ..Default::default()
+        };
+        assert!(!filter.accepts("/in/o", "/in/o/tmp/a.csv", 1));
+        assert!(!filter.accepts("/in/o", "/in/o/x/tmp/y/a.csv", 1));
+        assert!(!filter.accepts("/in/o", "/in/o/cache/old/a.csv", 1));
+        assert!(filter.accepts("/in/o", "/in/o/cache/new/a.csv", 1));
+    }
+
+    #[test]
+    fn exclude_wins_over_include() {
+        let filter = EntryFilter {
+            include: patterns(&["*.csv"]),
+            exclude: patterns(&["re:^draft_"]),
+            ..Default::default()
+        };
+        assert!(filter.accepts("/in/o", "/in/o/final.csv", 1));
+        assert!(!filter.accepts("/in/o", "/in/o/draft_1.csv", 1));
+    }
+
+    #[test]
+    fn size_bounds_are_inclusive() {
+        let filter = EntryFilter {
+            min_size: Some(10),
+            max_size: Some(20),
+            ..Default::default()
+        };
+        assert!(!filter.accepts("/in/o", "/in/o/a", 9));
+        assert!(filter.accepts("/in/o", "/in/o/a", 10));
+        assert!(filter.accepts("/in/o", "/in/o/a", 20));
+        assert!(!filter.accepts("/in/o", "/in/o/a", 21));
+    }
+
+    #[test]
+    fn hidden_files_and_folders_are_ignored() {
+        let filter = EntryFilter {
+            ignore_hidden: true,
+            ..Default::default()
+        };
+        assert!(!filter.accepts("/in/o", "/in/o/.part", 1));
+        assert!(!filter.accepts("/in/o", "/in/o/.git/config", 1));
+        assert!(filter.accepts("/in/o", "/in/o/a.txt", 1));
+    }
+}
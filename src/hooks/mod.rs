@@ -0,0 +1,428 @@
+use crate::{FtpDirEntry, Order};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::{fmt, process::Stdio, sync::Arc, time::Duration};
+use tokio::{io::AsyncWriteExt, process::Command};
+
+/// Order lifecycle moments hooks are called at
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum HookEvent {
+    ///Order is ready and about to be queued
+    OrderReady,
+    DownloadStarted,
+    FileDownloaded,
+    ///Every file is downloaded and verified locally, remote data is not handled yet
+    OrderCompleted,
+    ///Remote data was deleted or archived (move and archive modes)
+    RemoteCleaned,
+    OrderFailed,
+}
+
+/// What a failed hook means for the order; only hooks called before remote cleanup can affect it
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum FailurePolicy {
+    ///Failure is only reported
+    #[default]
+    Ignore,
+    ///Order is delivered, but remote data is neither deleted nor archived
+    KeepRemote,
+    ///Order is failed and retried according to the retry policy (not queued, for `order_ready`)
+    FailOrder,
+}
+
+#[derive(Debug, Clone)]
+pub struct HookError {
+    pub message: String,
+    pub policy: FailurePolicy,
+}
+impl HookError {
+    pub fn new(message: &str, policy: FailurePolicy) -> Self {
+        Self {
+            message: message.to_owned(),
+            policy,
+        }
+    }
+}
+impl fmt::Display for HookError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({:?})", self.message, self.policy)
+    }
+}
+
+pub type HookResult = std::result::Result<(), HookError>;
+
+/// Callbacks on order lifecycle, registered with `Watcher::add_hooks`;
+/// Every method does nothing by default
+#[async_trait]
+pub trait OrderHooks: Send + Sync + fmt::Debug {
+    async fn on_order_ready(&self, _order: &Order) -> HookResult {
+        Ok(())
+    }
+    async fn on_download_started(&self, _order: &Order) -> HookResult {
+        Ok(())
+    }
+    async fn on_file_downloaded(&self, _order: &Order, _file: &FtpDirEntry) -> HookResult {
+        Ok(())
+    }
+    async fn on_order_completed(&self, _order: &Order) -> HookResult {
+        Ok(())
+    }
+    async fn on_remote_cleaned(&self, _order: &Order) -> HookResult {
+        Ok(())
+    }
+    async fn on_order_failed(&self, _order: &Order, _error: &str) -> HookResult {
+        Ok(())
+    }
+}
+
+/// Shell command run on an order event, as described in config file
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CommandHook {
+    pub event: HookEvent,
+    ///Run with `sh -c`
+    pub command: String,
+    #[serde(default = "default_hook_timeout")]
+    pub timeout_sec: u64,
+    ///Applied when command exits with non-zero code or times out
+    #[serde(default)]
+    pub on_failure: FailurePolicy,
+}
+fn default_hook_timeout() -> u64 {
+    30
+}
+
+#[derive(Serialize, Debug)]
+struct HookFile {
+    path: String,
+    size: usize,
+}
+impl From<&FtpDirEntry> for HookFile {
+    fn from(entry: &FtpDirEntry) -> Self {
+        Self {
+            path: entry.get_full_path(),
+            size: entry.get_size(),
+        }
+    }
+}
+
+/// Order metadata passed to command hooks as JSON on stdin
+#[derive(Serialize, Debug)]
+struct HookPayload {
+    event: HookEvent,
+    order: String,
+    watch_dir: String,
+    sync_mode: String,
+    local_path: String,
+    files: Vec<HookFile>,
+    file: Option<HookFile>,
+    attempts: u32,
+    error: Option<String>,
+}
+impl HookPayload {
+    fn new(
+        event: HookEvent,
+        order: &Order,
+        file: Option<&FtpDirEntry>,
+        error: Option<&str>,
+    ) -> Self {
+        Self {
+            event,
+            order: order.get_root_path(),
+            watch_dir: order.get_watch_dir().path.to_owned(),
+            sync_mode: format!("{:?}", order.get_sync_mode()).to_lowercase(),
+            local_path: order.get_local_root_path(),
+            files: order
+                .get_files_list()
+                .unwrap_or_default()
+                .iter()
+                .map(HookFile::from)
+                .collect(),
+            file: file.map(HookFile::from),
+            attempts: order.get_attempts(),
+            error: error.map(|e| e.to_owned()),
+        }
+    }
+    ///Same metadata as environment variables
+    fn env(&self) -> Vec<(&'static str, String)> {
+        vec![
+            (
+                "FTP_SYNC_EVENT",
+                serde_json::to_value(self.event)
+                    .ok()
+                    .and_then(|v| v.as_str().map(|s| s.to_owned()))
+                    .unwrap_or_default(),
+            ),
+            ("FTP_SYNC_ORDER", self.order.to_owned()),
+            ("FTP_SYNC_WATCH_DIR", self.watch_dir.to_owned()),
+            ("FTP_SYNC_SYNC_MODE", self.sync_mode.to_owned()),
+            ("FTP_SYNC_LOCAL_PATH", self.local_path.to_owned()),
+            ("FTP_SYNC_FILES_COUNT", self.files.len().to_string()),
+            (
+                "FTP_SYNC_BYTES",
+                self.files.iter().map(|f| f.size).sum::<usize>().to_string(),
+            ),
+            (
+                "FTP_SYNC_FILE",
+                self.file
+                    .as_ref()
+                    .map(|f| f.path.to_owned())
+                    .unwrap_or_default(),
+            ),
+            ("FTP_SYNC_ATTEMPTS", self.attempts.to_string()),
+            ("FTP_SYNC_ERROR", self.error.to_owned().unwrap_or_default()),
+        ]
+    }
+}
+
+/// Runs configured shell commands on order events;
+/// Order metadata is passed as `FTP_SYNC_*` environment variables and as JSON on stdin
+#[derive(Debug)]
+pub struct CommandHooks(pub Vec<CommandHook>);
+impl CommandHooks {
+    async fn run(
+        &self,
+        event: HookEvent,
+        order: &Order,
+        file: Option<&FtpDirEntry>,
+        error: Option<&str>,
+    ) -> HookResult {
+        let mut result = Ok(());
+        for hook in self.0.iter().filter(|h| h.event == event) {
+            let payload = HookPayload::new(event, order, file, error);
+            if let Err(message) = run_command(hook, &payload).await {
+                let message = format!("command {:?} {}", hook.command, message);
+                result = worst(result, Err(HookError::new(&message, hook.on_failure)));
+            }
+        }
+        result
+    }
+}
+#[async_trait]
+impl OrderHooks for CommandHooks {
+    async fn on_order_ready(&self, order: &Order) -> HookResult {
+        self.run(HookEvent::OrderReady, order, None, None).await
+    }
+    async fn on_download_started(&self, order: &Order) -> HookResult {
+        self.run(HookEvent::DownloadStarted, order, None, None)
+            .await
+    }
+    async fn on_file_downloaded(&self, order: &Order, file: &FtpDirEntry) -> HookResult {
+        self.run(HookEvent::FileDownloaded, order, Some(file), None)
+            .await
+    }
+    async fn on_order_completed(&self, order: &Order) -> HookResult {
+        self.run(HookEvent::OrderCompleted, order, None, None).await
+    }
+    async fn on_remote_cleaned(&self, order: &Order) -> HookResult {
+        self.run(HookEvent::RemoteCleaned, order, None, None).await
+    }
+    async fn on_order_failed(&self, order: &Order, error: &str) -> HookResult {
+        self.run(HookEvent::OrderFailed, order, None, Some(error))
+            .await
+    }
+}
+
+async fn run_command(hook: &CommandHook, payload: &HookPayload) -> std::result::Result<(), String> {
+    let json = serde_json::to_vec(payload).map_err(|e| e.to_string())?;
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(&hook.command)
+        .envs(payload.env())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        //timed out command is killed when dropped
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| e.to_string())?;
+    let stdin = child.stdin.take();
+    let feed = async move {
+        if let Some(mut stdin) = stdin {
+            //command may not read stdin at all; large payload blocks until it does, hence the timeout
+            stdin.write_all(&json).await.ok();
+        }
+    };
+    let run = async {
+        let ((), output) = tokio::join!(feed, child.wait_with_output());
+        output
+    };
+    let output = tokio::time::timeout(Duration::from_secs(hook.timeout_sec), run)
+        .await
+        .map_err(|_| format!("timed out after {}s", hook.timeout_sec))?
+        .map_err(|e| e.to_string())?;
+    if !output.status.success() {
+        return Err(format!(
+            "exited with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(())
+}
+
+///Keep the error with the most severe policy
+fn worst(a: HookResult, b: HookResult) -> HookResult {
+    match (a, b) {
+        (Err(a), Err(b)) if b.policy > a.policy => Err(b),
+        (Err(a), _) => Err(a),
+        (Ok(()), b) => b,
+    }
+}
+
+/// All registered hooks, every one of them is called on every event
+#[derive(Debug, Default, Clone)]
+pub struct Hooks(Vec<Arc<dyn OrderHooks>>);
+impl Hooks {
+    pub fn add(&mut self, hooks: Arc<dyn OrderHooks>) {
+        self.0.push(hooks);
+    }
+    pub async fn order_ready(&self, order: &Order) -> HookResult {
+        let mut result = Ok(());
+        for h in self.0.iter() {
+            result = worst(result, h.on_order_ready(order).await);
+        }
+        result
+    }
+    pub async fn download_started(&self, order: &Order) -> HookResult {
+        let mut result = Ok(());
+        for h in self.0.iter() {
+            result = worst(result, h.on_download_started(order).await);
+        }
+        result
+    }
+    pub async fn file_downloaded(&self, order: &Order, file: &FtpDirEntry) -> HookResult {
+        let mut result = Ok(());
+        for h in self.0.iter() {
+            result = worst(result, h.on_file_downloaded(order, file).await);
+        }
+        result
+    }
+    pub async fn order_completed(&self, order: &Order) -> HookResult {
+        let mut result = Ok(());
+        for h in self.0.iter() {
+            result = worst(result, h.on_order_completed(order).await);
+        }
+        result
+    }
+    pub async fn remote_cleaned(&self, order: &Order) -> HookResult {
+        let mut result = Ok(());
+        for h in self.0.iter() {
+            result = worst(result, h.on_remote_cleaned(order).await);
+        }
+        result
+    }
+    pub async fn order_failed(&self, order: &Order, error: &str) -> HookResult {
+        let mut result = Ok(());
+        for h in self.0.iter() {
+            result = worst(result, h.on_order_failed(order, error).await);
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::WatchDir;
+    use std::collections::HashMap;
+
+    fn failed(policy: FailurePolicy) -> HookResult {
+        Err(HookError::new(&format!("{:?}", policy), policy))
+    }
+
+    fn policy(result: HookResult) -> Option<FailurePolicy> {
+        result.err().map(|e| e.policy)
+    }
+
+    fn order() -> Order {
+        let mut order = Order::new(
+            &FtpDirEntry::Folder("in/o".into()),
+            "/local",
+            &WatchDir::new("in"),
+        );
+        order.set_entries(
+            vec![
+                FtpDirEntry::File("in/o/a".into(), 3),
+                FtpDirEntry::File("in/o/b".into(), 4),
+            ],
+            Vec::new(),
+        );
+        order
+    }
+
+    fn hook(command: &str, timeout_sec: u64) -> CommandHook {
+        CommandHook {
+            event: HookEvent::OrderFailed,
+            command: command.to_owned(),
+            timeout_sec,
+            on_failure: FailurePolicy::FailOrder,
+        }
+    }
+
+    #[test]
+    fn worst_keeps_most_severe_policy() {
+        use FailurePolicy::*;
+        assert_eq!(policy(worst(Ok(()), Ok(()))), None);
+        assert_eq!(policy(worst(Ok(()), failed(Ignore))), Some(Ignore));
+        assert_eq!(policy(worst(failed(KeepRemote), Ok(()))), Some(KeepRemote));
+        assert_eq!(
+            policy(worst(failed(Ignore), failed(FailOrder))),
+            Some(FailOrder)
+        );
+        assert_eq!(
+            policy(worst(failed(FailOrder), failed(KeepRemote))),
+            Some(FailOrder)
+        );
+    }
+
+    #[test]
+    fn payload_env_describes_order() {
+        let payload = HookPayload::new(
+            HookEvent::OrderFailed,
+            &order(),
+            Some(&FtpDirEntry::File("in/o/a".into(), 3)),
+            Some("boom"),
+        );
+        let env: HashMap<&str, String> = payload.env().into_iter().collect();
+        assert_eq!(env["FTP_SYNC_EVENT"], "order_failed");
+        assert_eq!(env["FTP_SYNC_ORDER"], "in/o");
+        assert_eq!(env["FTP_SYNC_WATCH_DIR"], "in");
+        assert_eq!(env["FTP_SYNC_SYNC_MODE"], "move");
+        assert_eq!(env["FTP_SYNC_LOCAL_PATH"], "/local/o");
+        assert_eq!(env["FTP_SYNC_FILES_COUNT"], "2");
+        assert_eq!(env["FTP_SYNC_BYTES"], "7");
+        assert_eq!(env["FTP_SYNC_FILE"], "in/o/a");
+        assert_eq!(env["FTP_SYNC_ATTEMPTS"], "0");
+        assert_eq!(env["FTP_SYNC_ERROR"], "boom");
+    }
+
+    #[tokio::test]
+    async fn command_gets_env_and_json_on_stdin() {
+        let payload = HookPayload::new(HookEvent::OrderFailed, &order(), None, None);
+        let command = r#"test "$FTP_SYNC_ORDER" = in/o && grep -q '"order":"in/o"'"#;
+        assert_eq!(run_command(&hook(command, 5), &payload).await, Ok(()));
+    }
+
+    #[tokio::test]
+    async fn non_zero_exit_fails_with_stderr() {
+        let payload = HookPayload::new(HookEvent::OrderFailed, &order(), None, None);
+        let error = run_command(&hook("echo nope >&2; exit 3", 5), &payload)
+            .await
+            .unwrap_err();
+        assert!(error.starts_with("exited with"), "{}", error);
+        assert!(error.ends_with("nope"), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn command_running_past_timeout_fails() {
+        let payload = HookPayload::new(HookEvent::OrderFailed, &order(), None, None);
+        let started = std::time::Instant::now();
+        let error = run_command(&hook("sleep 10", 1), &payload)
+            .await
+            .unwrap_err();
+        assert_eq!(error, "timed out after 1s");
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+}
@@ -0,0 +1,614 @@
+use crate::state::{self, FileList};
+use crate::{FtpDirEntry, Manifest, Result, SyncMode};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io::{BufRead, Write};
+use std::path::PathBuf;
+use std::sync::{mpsc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// How many completed orders are remembered after compaction
+const COMPLETED_TO_KEEP: usize = 10_000;
+/// Records appended between compactions of an open journal
+const COMPACT_EVERY: usize = 10_000;
+
+/// Order lifecycle step persisted in the journal
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum JournalRecord {
+    ///Order accepted for download, with everything needed to resume it
+    Queued {
+        order: String,
+        watch_dir: String,
+        target: String,
+        files: FileList,
+        folders: Vec<String>,
+        manifest: Option<Manifest>,
+        ///Files left out by filters, deleted along with the order in move mode
+        #[serde(default)]
+        excluded: FileList,
+        ///Remote data of a resumed order is handled as when it was queued, whatever the config says now
+        #[serde(default)]
+        sync_mode: Option<SyncMode>,
+        ///Order is a single file, not a folder
+        #[serde(default)]
+        file_order: bool,
+    },
+    FileDownloaded {
+        order: String,
+        path: String,
+    },
+    ///All files downloaded and verified
+    Downloaded {
+        order: String,
+    },
+    ///Remote entry deleted, or moved to the archive folder in archive mode
+    RemoteDeleted {
+        order: String,
+        path: String,
+    },
+    Completed {
+        order: String,
+        signature: String,
+    },
+    Failed {
+        order: String,
+        attempts: u32,
+        error: String,
+    },
+    ///Retries exhausted; order waits for manual requeue
+    DeadLettered {
+        order: String,
+        attempts: u32,
+        error: String,
+    },
+    Requeued {
+        order: String,
+    },
+}
+impl JournalRecord {
+    pub fn order(&self) -> &str {
+        match self {
+            Self::Queued { order, .. }
+            | Self::FileDownloaded { order, .. }
+            | Self::Downloaded { order }
+            | Self::RemoteDeleted { order, .. }
+            | Self::Completed { order, .. }
+            | Self::Failed { order, .. }
+            | Self::DeadLettered { order, .. }
+            | Self::Requeued { order } => order,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct JournalLine {
+    ///unix time, seconds
+    at: u64,
+    #[serde(flatten)]
+    record: JournalRecord,
+}
+
+/// Incomplete order found in the journal on startup
+#[derive(Debug, Clone, Default)]
+pub struct ResumedOrder {
+    pub order: String,
+    pub watch_dir: String,
+    pub target: String,
+    pub files: FileList,
+    pub folders: Vec<String>,
+    pub excluded: FileList,
+    pub manifest: Option<Manifest>,
+    ///Not known for orders queued by older versions
+    pub sync_mode: Option<SyncMode>,
+    pub file_order: bool,
+    pub downloaded: bool,
+    pub deleted: HashSet<String>,
+    pub attempts: u32,
+    pub last_error: Option<String>,
+    pub dead_lettered: bool,
+}
+impl ResumedOrder {
+    ///Files still present remotely
+    pub fn remaining_files(&self) -> Vec<FtpDirEntry> {
+        self.files
+            .iter()
+            .filter(|file| !self.deleted.contains(&file.path))
+            .map(|file| FtpDirEntry::File(file.path.to_owned(), file.size))
+            .collect()
+    }
+    ///Excluded files still present remotely
+    pub fn remaining_excluded(&self) -> Vec<FtpDirEntry> {
+        self.excluded
+            .iter()
+            .filter(|file| !self.deleted.contains(&file.path))
+            .map(|file| FtpDirEntry::File(file.path.to_owned(), file.size))
+            .collect()
+    }
+    ///Listing dates of the order files
+    pub fn modified(&self) -> HashMap<String, i64> {
+        self.files
+            .iter()
+            .filter_map(|file| file.modified.map(|m| (file.path.to_owned(), m)))
+            .collect()
+    }
+    ///Folders still present remotely
+    pub fn remaining_folders(&self) -> Vec<FtpDirEntry> {
+        self.folders
+            .iter()
+            .filter(|path| !self.deleted.contains(*path))
+            .map(|path| FtpDirEntry::Folder(path.to_owned()))
+            .collect()
+    }
+}
+
+/// Append-only json-lines log of order lifecycle;
+/// Replayed on startup to resume interrupted orders and to never deliver a completed order twice;
+/// Records are written and synced in order by a thread of its own, so appending never blocks async tasks
+#[derive(Debug)]
+pub struct Journal {
+    path: PathBuf,
+    writer: Option<mpsc::Sender<JournalLine>>,
+    thread: Option<JoinHandle<()>>,
+    completed: Mutex<CompletedOrders>,
+}
+
+/// (order, signature) of a completed order
+type Completion = (String, String);
+
+/// Most recently completed orders, as many as compaction keeps
+#[derive(Debug, Default)]
+struct CompletedOrders {
+    set: HashSet<Completion>,
+    ///Oldest first
+    by_age: VecDeque<Completion>,
+}
+impl CompletedOrders {
+    fn insert(&mut self, completed: Completion) {
+        if !self.set.insert(completed.to_owned()) {
+            return;
+        }
+        self.by_age.push_back(completed);
+        while self.by_age.len() > COMPLETED_TO_KEEP {
+            if let Some(oldest) = self.by_age.pop_front() {
+                self.set.remove(&oldest);
+            }
+        }
+    }
+    fn contains(&self, completed: &Completion) -> bool {
+        self.set.contains(completed)
+    }
+}
+
+impl Journal {
+    ///Open journal, replay it and compact it to incomplete orders and remembered completed ones
+    pub fn open(path: PathBuf) -> Result<(Self, Vec<ResumedOrder>)> {
+        let (orders, completed) = Journal::compact(&path)?;
+        let file = Journal::open_for_append(&path)?;
+        let (writer, lines) = mpsc::channel();
+        let thread = std::thread::Builder::new().name("journal".into()).spawn({
+            let path = path.to_owned();
+            move || Journal::write_lines(path, file, lines)
+        })?;
+        let mut remembered = CompletedOrders::default();
+        for order in completed {
+            remembered.insert(order);
+        }
+        let journal = Self {
+            path,
+            writer: Some(writer),
+            thread: Some(thread),
+            completed: Mutex::new(remembered),
+        };
+        Ok((journal, orders.into_values().collect()))
+    }
+    ///Replay journal file and rewrite it with incomplete orders and remembered completed ones only
+    fn compact(path: &PathBuf) -> Result<(HashMap<String, ResumedOrder>, Vec<Completion>)> {
+        let lines = Journal::read_lines(path)?;
+        let mut orders: HashMap<String, ResumedOrder> = HashMap::new();
+        let mut completed: Vec<Completion> = Vec::new();
+        let mut kept: Vec<&JournalLine> = Vec::new();
+        for line in lines.iter() {
+            match &line.record {
+                JournalRecord::Queued {
+                    order,
+                    watch_dir,
+                    target,
+                    files,
+                    folders,
+                    manifest,
+                    excluded,
+                    sync_mode,
+                    file_order,
+                } => {
+                    orders.insert(
+                        order.to_owned(),
+                        ResumedOrder {
+                            order: order.to_owned(),
+                            watch_dir: watch_dir.to_owned(),
+                            target: target.to_owned(),
+                            files: files.to_owned(),
+                            folders: folders.to_owned(),
+                            excluded: excluded.to_owned(),
+                            manifest: manifest.to_owned(),
+                            sync_mode: *sync_mode,
+                            file_order: *file_order,
+                            ..Default::default()
+                        },
+                    );
+                }
+                //files present locally are skipped by size when the order is resumed
+                JournalRecord::FileDownloaded { .. } => {}
+                JournalRecord::Downloaded { order } => {
+                    if let Some(o) = orders.get_mut(order) {
+                        o.downloaded = true;
+                    }
+                }
+                JournalRecord::RemoteDeleted { order, path } => {
+                    if let Some(o) = orders.get_mut(order) {
+                        o.deleted.insert(path.to_owned());
+                    }
+                }
+                JournalRecord::Failed {
+                    order,
+                    attempts,
+                    error,
+                } => {
+                    if let Some(o) = orders.get_mut(order) {
+                        o.attempts = *attempts;
+                        o.last_error = Some(error.to_owned());
+                    }
+                }
+                JournalRecord::DeadLettered {
+                    order,
+                    attempts,
+                    error,
+                } => {
+                    if let Some(o) = orders.get_mut(order) {
+                        o.attempts = *attempts;
+                        o.last_error = Some(error.to_owned());
+                        o.dead_lettered = true;
+                    }
+                }
+                JournalRecord::Requeued { order } => {
+                    if let Some(o) = orders.get_mut(order) {
+                        o.attempts = 0;
+                        o.dead_lettered = false;
+                    }
+                }
+                JournalRecord::Completed { order, signature } => {
+                    orders.remove(order);
+                    completed.retain(|(o, _)| o != order);
+                    completed.push((order.to_owned(), signature.to_owned()));
+                }
+            }
+        }
+        let skip = completed.len().saturating_sub(COMPLETED_TO_KEEP);
+        let completed: Vec<Completion> = completed.into_iter().skip(skip).collect();
+        let remembered: HashSet<&Completion> = completed.iter().collect();
+        for line in lines.iter() {
+            let keep = match &line.record {
+                JournalRecord::Completed { order, signature } => {
+                    remembered.contains(&(order.to_owned(), signature.to_owned()))
+                }
+                record => orders.contains_key(record.order()),
+            };
+            if keep {
+                kept.push(line);
+            }
+        }
+        Journal::rewrite(path, &kept)?;
+        Ok((orders, completed))
+    }
+    fn open_for_append(path: &PathBuf) -> Result<std::fs::File> {
+        Ok(std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?)
+    }
+    pub fn get_path(&self) -> &PathBuf {
+        &self.path
+    }
+    ///Queue record to be written and flushed to disk; failures are reported but never stop the engine
+    pub fn append(&self, record: JournalRecord) {
+        if let JournalRecord::Completed { order, signature } = &record {
+            self.completed
+                .lock()
+                .expect("journal lock poisoned")
+                .insert((order.to_owned(), signature.to_owned()));
+        }
+        let line = JournalLine { at: now(), record };
+        let sent = self
+            .writer
+            .as_ref()
+            .is_some_and(|writer| writer.send(line).is_ok());
+        if !sent {
+            tracing::error!(path = ?self.path, "journal writer stopped, record lost");
+        }
+    }
+    ///Writer thread: append every record and sync it to disk, until the journal is dropped;
+    /// Compacts the journal every `COMPACT_EVERY` records, so completed orders don't pile up
+    fn write_lines(path: PathBuf, mut file: std::fs::File, lines: mpsc::Receiver<JournalLine>) {
+        for (written, line) in lines.into_iter().enumerate() {
+            let res = serde_json::to_string(&line)
+                .map_err(std::io::Error::other)
+                .and_then(|mut content| {
+                    content.push('\n');
+                    file.write_all(content.as_bytes())?;
+                    file.sync_data()
+                });
+            if let Err(e) = res {
+                tracing::error!(path = ?path, error = ?e, "failed to write journal");
+            }
+            if (written + 1) % COMPACT_EVERY == 0 {
+                let compacted =
+                    Journal::compact(&path).and_then(|_| Journal::open_for_append(&path));
+                match compacted {
+                    Ok(compacted) => file = compacted,
+                    Err(e) => {
+                        tracing::error!(path = ?path, error = ?e, "failed to compact journal")
+                    }
+                }
+            }
+        }
+    }
+    ///Whether exactly this order (same path and files) was completed already
+    pub fn is_completed(&self, order: &str, files: &[FtpDirEntry]) -> bool {
+        self.completed
+            .lock()
+            .expect("journal lock poisoned")
+            .contains(&(order.to_owned(), signature(files)))
+    }
+    fn read_lines(path: &PathBuf) -> Result<Vec<JournalLine>> {
+        let file = match std::fs::File::open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        let mut lines = Vec::new();
+        for line in std::io::BufReader::new(file).lines() {
+            //last line may be torn by a crash
+            if let Ok(parsed) = serde_json::from_str::<JournalLine>(&line?) {
+                lines.push(parsed);
+            }
+        }
+        Ok(lines)
+    }
+    //write to temp file first so a crash never loses the journal
+    fn rewrite(path: &PathBuf, lines: &[&JournalLine]) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let tmp = path.with_extension("tmp");
+        let mut file = std::fs::File::create(&tmp)?;
+        for line in lines {
+            serde_json::to_writer(&mut file, line)?;
+            file.write_all(b"\n")?;
+        }
+        file.sync_all()?;
+        std::fs::rename(&tmp, path)?;
+        Ok(())
+    }
+}
+
+//records queued before the journal is dropped are written
+impl Drop for Journal {
+    fn drop(&mut self) {
+        drop(self.writer.take());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+///Identity of order content: sha256 of its sorted (path, size) list
+pub fn signature(files: &[FtpDirEntry]) -> String {
+    let list = state::file_list(files);
+    let mut hasher = Sha256::new();
+    for file in list {
+        hasher.update(file.path.as_bytes());
+        hasher.update(file.size.to_le_bytes());
+    }
+    hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn journal_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "ftp_sync_journal_{}_{}.jsonl",
+            std::process::id(),
+            name
+        ));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    fn queued(order: &str) -> JournalRecord {
+        JournalRecord::Queued {
+            order: order.to_owned(),
+            watch_dir: "/in".into(),
+            target: "/local".into(),
+            files: state::dated_file_list(&files(order), |path| {
+                path.ends_with("/a").then_some(100)
+            }),
+            folders: vec![format!("{}/sub", order), order.to_owned()],
+            manifest: None,
+            excluded: state::file_list(&[FtpDirEntry::File(format!("{}/x.tmp", order), 3)]),
+            sync_mode: Some(SyncMode::Archive),
+            file_order: false,
+        }
+    }
+
+    fn files(order: &str) -> Vec<FtpDirEntry> {
+        vec![
+            FtpDirEntry::File(format!("{}/a", order), 1),
+            FtpDirEntry::File(format!("{}/b", order), 2),
+        ]
+    }
+
+    fn reopen(journal: Journal) -> (Journal, Vec<ResumedOrder>) {
+        let path = journal.get_path().to_owned();
+        //written by the writer thread once dropped
+        drop(journal);
+        Journal::open(path).unwrap()
+    }
+
+    fn line_count(path: &PathBuf) -> usize {
+        std::fs::read_to_string(path).unwrap().lines().count()
+    }
+
+    #[test]
+    fn resumes_interrupted_order_with_what_is_left_remotely() {
+        let path = journal_path("resume");
+        let (journal, resumed) = Journal::open(path.to_owned()).unwrap();
+        assert!(resumed.is_empty());
+        journal.append(queued("/in/o"));
+        journal.append(JournalRecord::FileDownloaded {
+            order: "/in/o".into(),
+            path: "/in/o/a".into(),
+        });
+        journal.append(JournalRecord::Downloaded {
+            order: "/in/o".into(),
+        });
+        for deleted in ["/in/o/a", "/in/o/x.tmp", "/in/o/sub"] {
+            journal.append(JournalRecord::RemoteDeleted {
+                order: "/in/o".into(),
+                path: deleted.into(),
+            });
+        }
+        let (_journal, resumed) = reopen(journal);
+        assert_eq!(resumed.len(), 1);
+        let order = &resumed[0];
+        assert_eq!(order.order, "/in/o");
+        assert!(order.downloaded);
+        assert_eq!(order.sync_mode, Some(SyncMode::Archive));
+        assert_eq!(
+            order.remaining_files(),
+            vec![FtpDirEntry::File("/in/o/b".into(), 2)]
+        );
+        assert!(order.remaining_excluded().is_empty());
+        assert_eq!(
+            order.modified(),
+            HashMap::from([("/in/o/a".to_owned(), 100)])
+        );
+        assert_eq!(
+            order.remaining_folders(),
+            vec![FtpDirEntry::Folder("/in/o".into())]
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn completed_order_is_pruned_but_remembered() {
+        let path = journal_path("completed");
+        let (journal, _) = Journal::open(path.to_owned()).unwrap();
+        journal.append(queued("/in/o"));
+        journal.append(JournalRecord::Downloaded {
+            order: "/in/o".into(),
+        });
+        journal.append(JournalRecord::Completed {
+            order: "/in/o".into(),
+            signature: signature(&files("/in/o")),
+        });
+        assert!(journal.is_completed("/in/o", &files("/in/o")));
+        let (journal, resumed) = reopen(journal);
+        assert!(resumed.is_empty());
+        assert_eq!(line_count(&path), 1);
+        assert!(journal.is_completed("/in/o", &files("/in/o")));
+        //same path with other content is a new order
+        assert!(!journal.is_completed("/in/o", &[FtpDirEntry::File("/in/o/a".into(), 5)]));
+        drop(journal);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn failures_dead_letters_and_requeues_are_replayed() {
+        let path = journal_path("failures");
+        let (journal, _) = Journal::open(path.to_owned()).unwrap();
+        journal.append(queued("/in/failed"));
+        journal.append(JournalRecord::Failed {
+            order: "/in/failed".into(),
+            attempts: 1,
+            error: "timeout".into(),
+        });
+        journal.append(queued("/in/dead"));
+        journal.append(JournalRecord::DeadLettered {
+            order: "/in/dead".into(),
+            attempts: 5,
+            error: "refused".into(),
+        });
+        journal.append(queued("/in/requeued"));
+        journal.append(JournalRecord::DeadLettered {
+            order: "/in/requeued".into(),
+            attempts: 5,
+            error: "refused".into(),
+        });
+        journal.append(JournalRecord::Requeued {
+            order: "/in/requeued".into(),
+        });
+        let (_journal, resumed) = reopen(journal);
+        let find = |order: &str| resumed.iter().find(|o| o.order == order).unwrap();
+        let failed = find("/in/failed");
+        assert_eq!(failed.attempts, 1);
+        assert_eq!(failed.last_error.as_deref(), Some("timeout"));
+        assert!(!failed.dead_lettered);
+        let dead = find("/in/dead");
+        assert_eq!(dead.attempts, 5);
+        assert!(dead.dead_lettered);
+        let requeued = find("/in/requeued");
+        assert_eq!(requeued.attempts, 0);
+        assert!(!requeued.dead_lettered);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn torn_last_line_is_skipped() {
+        let path = journal_path("torn");
+        let (journal, _) = Journal::open(path.to_owned()).unwrap();
+        journal.append(queued("/in/o"));
+        drop(journal);
+        let mut file = Journal::open_for_append(&path).unwrap();
+        file.write_all(br#"{"at":1,"event":"comple"#).unwrap();
+        let (_journal, resumed) = Journal::open(path.to_owned()).unwrap();
+        assert_eq!(resumed.len(), 1);
+        assert_eq!(line_count(&path), 1);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn only_most_recent_completed_orders_are_kept() {
+        let path = journal_path("prune");
+        let lines: Vec<JournalLine> = (0..=COMPLETED_TO_KEEP)
+            .map(|i| JournalLine {
+                at: 0,
+                record: JournalRecord::Completed {
+                    order: format!("/in/{}", i),
+                    signature: signature(&files(&format!("/in/{}", i))),
+                },
+            })
+            .collect();
+        Journal::rewrite(&path, &lines.iter().collect::<Vec<_>>()).unwrap();
+        let (journal, _) = Journal::open(path.to_owned()).unwrap();
+        assert_eq!(line_count(&path), COMPLETED_TO_KEEP);
+        assert!(!journal.is_completed("/in/0", &files("/in/0")));
+        assert!(journal.is_completed("/in/1", &files("/in/1")));
+        drop(journal);
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -1,17 +1,62 @@
+mod admin;
+mod audit;
+mod breaker;
 mod config;
 mod connection;
+mod control;
 mod controller;
 mod error;
+mod events;
+mod filter;
+mod hooks;
+mod journal;
+mod logger;
+mod manifest;
+mod metrics;
 mod order;
 mod pool;
+mod progress;
+mod readiness;
+mod scheduler;
+mod state;
+mod status;
+mod uploader;
 mod utils;
 mod watcher;
 
-pub use config::Config;
-pub use connection::{Connection, Result};
+#[cfg(feature = "admin")]
+pub use admin::serve as serve_admin;
+pub use admin::AdminConfig;
+pub use audit::{AuditAction, AuditConfig, AuditFormat, AuditLog, AuditRecord};
+pub use breaker::{Backoff, CircuitState};
+pub use config::{
+    AfterUpload, Config, HealthCheck, ParallelConfig, ReconnectConfig, RecycleConfig, RetryPolicy,
+    SyncMode, UploadDir, WatchDir,
+};
+pub use connection::{Activity, Connection, ConnectionStats, HealthHistory, Result};
+pub use control::{Paused, Work};
 pub use controller::Controller;
 pub use error::CustomError;
-pub use order::Order;
-pub use pool::Pool;
-pub use utils::FtpDirEntry;
+pub use events::{ErrorKind, Event, EventStream};
+pub use filter::EntryFilter;
+pub use hooks::{
+    CommandHook, CommandHooks, FailurePolicy, HookError, HookEvent, HookResult, Hooks, OrderHooks,
+};
+pub use journal::{Journal, JournalRecord, ResumedOrder};
+pub use logger::{
+    init as init_logging, LogConfig, LogFile, LogFormat, LogGuard, Rotation, TraceConfig,
+};
+pub use manifest::{Manifest, ManifestEntry};
+pub use order::{Order, OrderState, WorkerConnection};
+pub use pool::{ConnectionRole, ConnectionState, ConnectionStatus, Pool};
+pub use progress::{FileProgress, OrderProgress, Progress};
+pub use readiness::{
+    All, Any, EntrySource, FlagExtension, FlagFile, FlagPattern, LocalFs, ManifestFlag, MinAge,
+    ReadinessConfig, ReadinessContext, ReadinessStrategy, Stable,
+};
+pub use scheduler::{PriorityRule, SchedulePolicy, Scheduler, SchedulerConfig};
+pub use state::SyncState;
+pub use status::{Counters, OrderSummary, ScanStatus, Status};
+pub use uploader::Uploader;
+pub use utils::{FtpDirEntry, ListedEntry, NamePattern};
 pub use watcher::Watcher;
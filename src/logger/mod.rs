@@ -1,8 +1,149 @@
 /*
-    logger will log: 
+    logger logs:
 1. order download result
 2. remote folder delete result
 3. connection failure
 4. connection restore
 5. new folder ready to be downloaded
-*/
\ No newline at end of file
+
+    Library code only emits `tracing` events and spans (one span per order, one per connection);
+    `init` installs a subscriber for applications that don't have their own
+
+    Spans:
+    scan        per watched folder; watch_dir, orders_found, error
+      list      LIST; path, entries
+    order       per order attempt; path, watch_dir, files, size, error
+      download  RETR of a single file; path, local_path, size
+      cleanup   deleting or archiving remote order; mode
+        delete  DELE or RMD of a single entry; command, path, size
+    connection  maintenance of a connection by the controller; id, host
+      restore   reconnect and login; host
+    Failed spans get `error`, `reply_code` if the server sent one, and `otel.status_code`;
+    numbers are recorded as i64, unsigned ones would be exported as strings
+*/
+use crate::{CustomError, Result};
+use serde::{Deserialize, Serialize};
+use tracing::Span;
+use tracing_appender::{non_blocking::WorkerGuard, rolling};
+use tracing_subscriber::{fmt, layer::SubscriberExt, util::SubscriberInitExt, EnvFilter, Layer};
+
+#[cfg(feature = "otel")]
+mod otel;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Text,
+    ///One json object per line
+    Json,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Rotation {
+    Minutely,
+    Hourly,
+    #[default]
+    Daily,
+    Never,
+}
+
+/// Log files, a new one is started every `rotation` period
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct LogFile {
+    pub dir: String,
+    ///File name, the date of its period is appended
+    #[serde(default = "default_log_prefix")]
+    pub prefix: String,
+    #[serde(default)]
+    pub rotation: Rotation,
+}
+fn default_log_prefix() -> String {
+    "ftp_sync.log".into()
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct LogConfig {
+    ///Level ("error", "warn", "info", "debug", "trace") or a filter directive ("ftp_sync=debug")
+    pub level: String,
+    pub format: LogFormat,
+    ///Log to rotating files instead of stdout
+    pub file: Option<LogFile>,
+    ///Export spans to OpenTelemetry, needs feature "otel"
+    pub traces: Option<TraceConfig>,
+}
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            level: "info".into(),
+            format: LogFormat::default(),
+            file: None,
+            traces: None,
+        }
+    }
+}
+
+/// Spans are written as OTLP json lines when they end; the level filter applies to them too
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct TraceConfig {
+    ///Append to this file instead of writing to stdout
+    #[serde(default)]
+    pub file: Option<String>,
+    #[serde(default = "default_service_name")]
+    pub service_name: String,
+}
+fn default_service_name() -> String {
+    "ftp_sync".into()
+}
+
+/// Keeps background log writer running; logs written after it is dropped may be lost
+pub struct LogGuard {
+    _guard: WorkerGuard,
+}
+
+///Install global log subscriber described by config;
+/// Fails if the application installed one already
+pub fn init(config: &LogConfig) -> Result<LogGuard> {
+    let filter = EnvFilter::try_new(&config.level).map_err(|e| CustomError::Io(e.to_string()))?;
+    let (writer, guard) = match &config.file {
+        Some(file) => {
+            let appender = match file.rotation {
+                Rotation::Minutely => rolling::minutely(&file.dir, &file.prefix),
+                Rotation::Hourly => rolling::hourly(&file.dir, &file.prefix),
+                Rotation::Daily => rolling::daily(&file.dir, &file.prefix),
+                Rotation::Never => rolling::never(&file.dir, &file.prefix),
+            };
+            tracing_appender::non_blocking(appender)
+        }
+        None => tracing_appender::non_blocking(std::io::stdout()),
+    };
+    let layer = fmt::layer()
+        .with_writer(writer)
+        .with_ansi(config.file.is_none());
+    let layer = match config.format {
+        LogFormat::Text => layer.boxed(),
+        LogFormat::Json => layer.json().boxed(),
+    };
+    let registry = tracing_subscriber::registry().with(filter).with(layer);
+    #[cfg(feature = "otel")]
+    let registry = registry.with(config.traces.as_ref().map(otel::layer).transpose()?);
+    registry
+        .try_init()
+        .map_err(|e| CustomError::Io(e.to_string()))?;
+    #[cfg(not(feature = "otel"))]
+    if config.traces.is_some() {
+        tracing::warn!("traces are not exported, ftp_sync is built without feature \"otel\"");
+    }
+    Ok(LogGuard { _guard: guard })
+}
+
+///Mark span as failed; its fields `error`, `reply_code` and `otel.status_code` must be declared
+pub(crate) fn record_error(span: &Span, e: &CustomError) {
+    span.record("error", tracing::field::debug(e));
+    if let Some(code) = e.reply_code() {
+        span.record("reply_code", i64::from(code));
+    }
+    span.record("otel.status_code", "ERROR");
+}
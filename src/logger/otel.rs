@@ -0,0 +1,171 @@
+/*
+    Span exporter writing OTLP json, one ExportTraceServiceRequest per line, like the file exporter
+    of the OpenTelemetry collector; the collector's `otlpjsonfile` receiver can read it back
+*/
+use super::TraceConfig;
+use crate::Result;
+use futures::future::BoxFuture;
+use opentelemetry::trace::{SpanId, SpanKind, Status, TracerProvider as _};
+use opentelemetry::{Array, KeyValue, Value};
+use opentelemetry_sdk::export::trace::{ExportResult, SpanData, SpanExporter};
+use opentelemetry_sdk::trace::TracerProvider;
+use opentelemetry_sdk::Resource;
+use serde_json::{json, Value as Json};
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::Subscriber;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+
+///Layer turning tracing spans into OpenTelemetry spans, exported as soon as they end
+pub fn layer<S>(config: &TraceConfig) -> Result<impl Layer<S>>
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+{
+    let writer: Box<dyn Write + Send + Sync> = match &config.file {
+        Some(path) => Box::new(OpenOptions::new().create(true).append(true).open(path)?),
+        None => Box::new(std::io::stdout()),
+    };
+    let provider = TracerProvider::builder()
+        .with_simple_exporter(JsonExporter {
+            writer,
+            resource: Resource::default(),
+        })
+        .with_resource(Resource::default().merge(&Resource::new([KeyValue::new(
+            "service.name",
+            config.service_name.to_owned(),
+        )])))
+        .build();
+    let tracer = provider.tracer("ftp_sync");
+    Ok(tracing_opentelemetry::layer().with_tracer(tracer))
+}
+
+struct JsonExporter {
+    writer: Box<dyn Write + Send + Sync>,
+    resource: Resource,
+}
+impl fmt::Debug for JsonExporter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("JsonExporter")
+    }
+}
+
+impl SpanExporter for JsonExporter {
+    fn export(&mut self, batch: Vec<SpanData>) -> BoxFuture<'static, ExportResult> {
+        let resource: Vec<Json> = self
+            .resource
+            .iter()
+            .map(|(k, v)| attribute(k.as_str(), v))
+            .collect();
+        let mut res = Ok(());
+        for span in batch {
+            let line = json!({
+                "resourceSpans": [{
+                    "resource": { "attributes": resource },
+                    "scopeSpans": [{
+                        "scope": { "name": span.instrumentation_scope.name() },
+                        "spans": [to_json(&span)],
+                    }],
+                }],
+            });
+            //a single write per line, so lines of concurrent spans don't interleave
+            let mut line = line.to_string();
+            line.push('\n');
+            if let Err(e) = self.writer.write_all(line.as_bytes()) {
+                res = Err(e.to_string().into());
+            }
+        }
+        Box::pin(std::future::ready(res))
+    }
+    fn set_resource(&mut self, resource: &Resource) {
+        self.resource = resource.clone();
+    }
+}
+
+fn to_json(span: &SpanData) -> Json {
+    let (code, message) = match &span.status {
+        Status::Unset => (0, ""),
+        Status::Ok => (1, ""),
+        Status::Error { description } => (2, description.as_ref()),
+    };
+    let events: Vec<Json> = span
+        .events
+        .iter()
+        .map(|e| {
+            json!({
+                "timeUnixNano": nanos(e.timestamp),
+                "name": e.name,
+                "attributes": attributes(&e.attributes),
+            })
+        })
+        .collect();
+    let mut out = json!({
+        "traceId": span.span_context.trace_id().to_string(),
+        "spanId": span.span_context.span_id().to_string(),
+        "name": span.name,
+        "kind": kind(&span.span_kind),
+        "startTimeUnixNano": nanos(span.start_time),
+        "endTimeUnixNano": nanos(span.end_time),
+        "attributes": attributes(&span.attributes),
+        "events": events,
+        "status": { "code": code, "message": message },
+    });
+    if span.parent_span_id != SpanId::INVALID {
+        out["parentSpanId"] = span.parent_span_id.to_string().into();
+    }
+    out
+}
+
+fn kind(kind: &SpanKind) -> u8 {
+    match kind {
+        SpanKind::Internal => 1,
+        SpanKind::Server => 2,
+        SpanKind::Client => 3,
+        SpanKind::Producer => 4,
+        SpanKind::Consumer => 5,
+    }
+}
+
+fn attributes(kvs: &[KeyValue]) -> Vec<Json> {
+    kvs.iter()
+        .map(|kv| attribute(kv.key.as_str(), &kv.value))
+        .collect()
+}
+fn attribute(key: &str, value: &Value) -> Json {
+    json!({ "key": key, "value": any_value(value) })
+}
+///int64 values are strings in OTLP json
+fn any_value(value: &Value) -> Json {
+    match value {
+        Value::Bool(b) => json!({ "boolValue": b }),
+        Value::I64(i) => json!({ "intValue": i.to_string() }),
+        Value::F64(f) => json!({ "doubleValue": f }),
+        Value::String(s) => json!({ "stringValue": s.as_str() }),
+        Value::Array(array) => {
+            let values: Vec<Json> = match array {
+                Array::Bool(v) => v.iter().map(|b| json!({ "boolValue": b })).collect(),
+                Array::I64(v) => v
+                    .iter()
+                    .map(|i| json!({ "intValue": i.to_string() }))
+                    .collect(),
+                Array::F64(v) => v.iter().map(|f| json!({ "doubleValue": f })).collect(),
+                Array::String(v) => v
+                    .iter()
+                    .map(|s| json!({ "stringValue": s.as_str() }))
+                    .collect(),
+                other => vec![json!({ "stringValue": other.to_string() })],
+            };
+            json!({ "arrayValue": { "values": values } })
+        }
+        other => json!({ "stringValue": other.to_string() }),
+    }
+}
+
+fn nanos(time: SystemTime) -> String {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or_default()
+        .to_string()
+}
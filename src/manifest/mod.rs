@@ -0,0 +1,261 @@
+use crate::{CustomError, Result};
+use md5::Md5;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::Path;
+use tokio::io::AsyncReadExt;
+
+/// One file listed by a manifest flag file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    ///Path relative to the order folder
+    #[serde(alias = "path")]
+    pub name: String,
+    pub size: Option<u64>,
+    ///Hex digest; md5 (32 chars) or sha256 (64 chars)
+    #[serde(alias = "sha256", alias = "md5", alias = "checksum")]
+    pub hash: Option<String>,
+}
+
+/// Listing of every file the producer wrote into an order
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub files: Vec<ManifestEntry>,
+}
+
+impl Manifest {
+    ///Parse manifest content, detecting its format:
+    /// JSON (`[{"name", "size", "hash"}]` or `{"files": [...]}`), CSV (`name,size,hash`)
+    /// or plain `name size hash` lines; size and hash may be omitted
+    pub fn parse(content: &[u8]) -> Result<Self> {
+        let text = String::from_utf8_lossy(content);
+        let text = text.trim_start_matches('\u{feff}').trim();
+        if text.starts_with('[') {
+            let files: Vec<ManifestEntry> = serde_json::from_str(text)?;
+            return Self { files }.normalized();
+        }
+        if text.starts_with('{') {
+            let manifest: Self = serde_json::from_str(text)?;
+            return manifest.normalized();
+        }
+        let mut files = Vec::new();
+        for line in text.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields: Vec<&str> = if line.contains(',') {
+                line.split(',')
+                    .map(|f| f.trim().trim_matches('"'))
+                    .collect()
+            } else {
+                line.split_whitespace().collect()
+            };
+            let size = match fields.get(1) {
+                Some(size) => match size.parse::<u64>() {
+                    Ok(size) => Some(size),
+                    //csv header line
+                    Err(_) if files.is_empty() => continue,
+                    Err(_) => {
+                        return Err(CustomError::Serde(format!(
+                            "invalid size in manifest line: {}",
+                            line
+                        )))
+                    }
+                },
+                None => None,
+            };
+            files.push(ManifestEntry {
+                name: fields[0].to_owned(),
+                size,
+                hash: fields
+                    .get(2)
+                    .filter(|h| !h.is_empty())
+                    .map(|h| h.to_string()),
+            });
+        }
+        Self { files }.normalized()
+    }
+
+    ///Same path and hash spelling for every format: no leading "./", lowercase hex;
+    /// hashes must be md5 or sha256 digests, so a bad one is caught before anything is downloaded
+    fn normalized(mut self) -> Result<Self> {
+        for entry in self.files.iter_mut() {
+            entry.name = entry.name.trim_start_matches("./").to_owned();
+            entry.hash = entry.hash.take().map(|h| h.to_lowercase());
+            if let Some(hash) = &entry.hash {
+                if !matches!(hash.len(), 32 | 64) || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+                    return Err(CustomError::Serde(format!(
+                        "invalid hash {:?} of {} in manifest, expected md5 or sha256 hex digest",
+                        hash, entry.name
+                    )));
+                }
+            }
+        }
+        Ok(self)
+    }
+
+    ///Check every listed file is present with the right size;
+    /// `present` returns size of the file at the relative path, None if it is missing
+    pub fn is_satisfied_by(&self, present: impl Fn(&str) -> Option<u64>) -> bool {
+        self.files.iter().all(|entry| match present(&entry.name) {
+            Some(size) => entry.size.is_none_or(|expected| expected == size),
+            None => false,
+        })
+    }
+
+    ///Verify downloaded files in `local_root` against sizes and hashes of the manifest
+    pub async fn verify_local(&self, local_root: &str) -> Result<()> {
+        for entry in self.files.iter() {
+            let path = Path::new(local_root).join(&entry.name);
+            let meta = tokio::fs::metadata(&path).await.map_err(|e| {
+                CustomError::Io(format!("manifest file {:?} is missing: {}", path, e))
+            })?;
+            if let Some(size) = entry.size {
+                if meta.len() != size {
+                    return Err(CustomError::Io(format!(
+                        "manifest file {:?} has size {}, expected {}",
+                        path,
+                        meta.len(),
+                        size
+                    )));
+                }
+            }
+            if let Some(expected) = &entry.hash {
+                let actual = hash_file(&path, expected.len()).await?;
+                if actual != *expected {
+                    return Err(CustomError::Io(format!(
+                        "manifest file {:?} has hash {}, expected {}",
+                        path, actual, expected
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+///Hex digest of the file; algorithm picked by expected digest length (32 - md5, 64 - sha256)
+pub async fn hash_file(path: &Path, hex_len: usize) -> Result<String> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let mut buf = vec![0u8; 64 * 1024];
+    let mut md5 = Md5::new();
+    let mut sha256 = Sha256::new();
+    loop {
+        let n = file.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        match hex_len {
+            32 => md5.update(&buf[..n]),
+            _ => sha256.update(&buf[..n]),
+        }
+    }
+    let digest = match hex_len {
+        32 => md5.finalize().to_vec(),
+        64 => sha256.finalize().to_vec(),
+        _ => {
+            return Err(CustomError::Serde(format!(
+                "unsupported hash length {} in manifest",
+                hex_len
+            )))
+        }
+    };
+    Ok(digest.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const H256: &str = "abababababababababababababababababababababababababababababababab";
+    const H128: &str = "abcdabcdabcdabcdabcdabcdabcdabcd";
+
+    fn entry(name: &str, size: Option<u64>, hash: Option<&str>) -> ManifestEntry {
+        ManifestEntry {
+            name: name.to_owned(),
+            size,
+            hash: hash.map(|h| h.to_owned()),
+        }
+    }
+
+    #[test]
+    fn parses_json_array_with_aliases() {
+        let manifest = Manifest::parse(
+            format!(
+                r#"[{{"path": "./a.txt", "size": 3, "sha256": "{}"}}, {{"name": "b"}}]"#,
+                H256.to_uppercase()
+            )
+            .as_bytes(),
+        )
+        .unwrap();
+        assert_eq!(
+            manifest.files,
+            vec![entry("a.txt", Some(3), Some(H256)), entry("b", None, None)]
+        );
+    }
+
+    #[test]
+    fn parses_json_object() {
+        let manifest = Manifest::parse(br#"{"files": [{"name": "a", "size": 1}]}"#).unwrap();
+        assert_eq!(manifest.files, vec![entry("a", Some(1), None)]);
+    }
+
+    #[test]
+    fn json_detection_ignores_bom_and_whitespace() {
+        let manifest = Manifest::parse("\u{feff}\n  [{\"name\": \"a\"}]".as_bytes()).unwrap();
+        assert_eq!(manifest.files, vec![entry("a", None, None)]);
+    }
+
+    #[test]
+    fn csv_skips_header_and_lowercases_hash() {
+        let manifest = Manifest::parse(
+            b"name,size,hash\n\"./data/a.csv\",10,ABCDABCDABCDABCDABCDABCDABCDABCD\nb.csv, 20 ,\n",
+        )
+        .unwrap();
+        assert_eq!(
+            manifest.files,
+            vec![
+                entry("data/a.csv", Some(10), Some(H128)),
+                entry("b.csv", Some(20), None)
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_size_after_first_entry_is_an_error() {
+        assert!(Manifest::parse(b"a,1\nb,oops\n").is_err());
+    }
+
+    #[test]
+    fn hash_that_is_not_md5_or_sha256_hex_is_an_error() {
+        assert!(Manifest::parse(b"a 1 abcd\n").is_err());
+        assert!(Manifest::parse(format!("a 1 {}z\n", &H128[1..]).as_bytes()).is_err());
+        assert!(Manifest::parse(br#"[{"name": "a", "md5": "ff"}]"#).is_err());
+    }
+
+    #[test]
+    fn plain_lines_skip_comments_and_blank_lines() {
+        let manifest =
+            Manifest::parse(format!("# order 42\n\na.bin 5 {}\nb.bin\n", H128).as_bytes()).unwrap();
+        assert_eq!(
+            manifest.files,
+            vec![
+                entry("a.bin", Some(5), Some(H128)),
+                entry("b.bin", None, None)
+            ]
+        );
+    }
+
+    #[test]
+    fn satisfied_only_when_every_file_is_present_with_its_size() {
+        let manifest = Manifest {
+            files: vec![entry("a", Some(3), None), entry("b", None, None)],
+        };
+        assert!(manifest.is_satisfied_by(|name| match name {
+            "a" => Some(3),
+            _ => Some(100),
+        }));
+        assert!(!manifest.is_satisfied_by(|name| (name == "a").then_some(3)));
+        assert!(!manifest.is_satisfied_by(|_| Some(4)));
+    }
+}
@@ -0,0 +1,13 @@
+/*
+    Prometheus metrics (feature "metrics"), labelled by profile (user@host:port) and watched folder;
+    Without the feature every recording function does nothing
+*/
+#[cfg(feature = "metrics")]
+mod prom;
+#[cfg(feature = "metrics")]
+pub(crate) use prom::*;
+
+#[cfg(not(feature = "metrics"))]
+mod noop;
+#[cfg(not(feature = "metrics"))]
+pub(crate) use noop::*;
@@ -0,0 +1,15 @@
+use crate::ConnectionRole;
+use std::time::Duration;
+
+pub fn order_discovered(_profile: &str, _watch_dir: &str) {}
+pub fn order_completed(_profile: &str, _watch_dir: &str) {}
+pub fn order_failed(_profile: &str, _watch_dir: &str) {}
+pub fn file_downloaded(_profile: &str, _watch_dir: &str, _bytes: u64) {}
+pub fn file_uploaded(_profile: &str, _upload_dir: &str, _bytes: u64) {}
+pub fn upload_completed(_profile: &str, _upload_dir: &str) {}
+pub fn upload_failed(_profile: &str, _upload_dir: &str) {}
+pub fn remote_deleted(_profile: &str, _watch_dir: &str) {}
+pub fn scan_finished(_profile: &str, _watch_dir: &str, _duration: Duration, _ok: bool) {}
+pub fn command(_profile: &str, _command: &str, _duration: Duration) {}
+pub fn reconnect(_profile: &str, _success: bool) {}
+pub fn checkout_exhausted(_profile: &str, _role: ConnectionRole) {}
@@ -0,0 +1,243 @@
+use crate::{ConnectionRole, ConnectionState, Status};
+use once_cell::sync::Lazy;
+use prometheus::{
+    exponential_buckets, HistogramOpts, HistogramVec, IntCounterVec, IntGaugeVec, Opts, Registry,
+    TextEncoder,
+};
+use std::time::Duration;
+
+struct Metrics {
+    registry: Registry,
+    orders_discovered: IntCounterVec,
+    orders_completed: IntCounterVec,
+    orders_failed: IntCounterVec,
+    files_downloaded: IntCounterVec,
+    bytes_downloaded: IntCounterVec,
+    files_uploaded: IntCounterVec,
+    bytes_uploaded: IntCounterVec,
+    uploads_completed: IntCounterVec,
+    uploads_failed: IntCounterVec,
+    remote_deleted: IntCounterVec,
+    scan_duration: HistogramVec,
+    scan_errors: IntCounterVec,
+    command_duration: HistogramVec,
+    reconnects: IntCounterVec,
+    connections: IntGaugeVec,
+    checkout_exhausted: IntCounterVec,
+    queue_depth: IntGaugeVec,
+    running_orders: IntGaugeVec,
+}
+
+static METRICS: Lazy<Metrics> = Lazy::new(Metrics::new);
+
+impl Metrics {
+    fn new() -> Self {
+        let registry = Registry::new_custom(Some("ftp_sync".into()), None)
+            .expect("metrics registry prefix is valid");
+        let counter = |name: &str, help: &str, labels: &[&str]| {
+            let metric = IntCounterVec::new(Opts::new(name, help), labels)
+                .expect("metric definition is valid");
+            registry
+                .register(Box::new(metric.clone()))
+                .expect("metric is registered once");
+            metric
+        };
+        let gauge = |name: &str, help: &str, labels: &[&str]| {
+            let metric = IntGaugeVec::new(Opts::new(name, help), labels)
+                .expect("metric definition is valid");
+            registry
+                .register(Box::new(metric.clone()))
+                .expect("metric is registered once");
+            metric
+        };
+        //10ms .. ~20min
+        let histogram = |name: &str, help: &str, labels: &[&str]| {
+            let opts = HistogramOpts::new(name, help)
+                .buckets(exponential_buckets(0.01, 4.0, 10).expect("histogram buckets are valid"));
+            let metric = HistogramVec::new(opts, labels).expect("metric definition is valid");
+            registry
+                .register(Box::new(metric.clone()))
+                .expect("metric is registered once");
+            metric
+        };
+        let dir = &["profile", "watch_dir"];
+        Self {
+            orders_discovered: counter("orders_discovered_total", "Orders queued for download", dir),
+            orders_completed: counter("orders_completed_total", "Orders delivered", dir),
+            orders_failed: counter("orders_failed_total", "Failed order attempts", dir),
+            files_downloaded: counter("files_downloaded_total", "Files present locally", dir),
+            bytes_downloaded: counter("bytes_downloaded_total", "Bytes of files present locally", dir),
+            files_uploaded: counter("files_uploaded_total", "Files stored on the server", dir),
+            bytes_uploaded: counter("bytes_uploaded_total", "Bytes of files stored on the server", dir),
+            uploads_completed: counter("uploads_completed_total", "Orders uploaded", dir),
+            uploads_failed: counter("uploads_failed_total", "Failed upload attempts", dir),
+            remote_deleted: counter(
+                "remote_entries_deleted_total",
+                "Files and folders deleted remotely",
+                dir,
+            ),
+            scan_duration: histogram("scan_duration_seconds", "Scans of watched folders", dir),
+            scan_errors: counter("scan_errors_total", "Failed scans of watched folders", dir),
+            command_duration: histogram(
+                "command_duration_seconds",
+                "Ftp commands: LIST, RETR and STOR (whole transfer), DELE, RMD",
+                &["profile", "command"],
+            ),
+            reconnects: counter(
+                "reconnects_total",
+                "Reconnect attempts",
+                &["profile", "result"],
+            ),
+            connections: gauge(
+                "pool_connections",
+                "Pool connections",
+                &["profile", "role", "state"],
+            ),
+            checkout_exhausted: counter(
+                "pool_checkout_exhausted_total",
+                "Checkouts that found every connection of the role busy and have to wait for the next tick",
+                &["profile", "role"],
+            ),
+            queue_depth: gauge("queue_depth", "Orders waiting for download", &["profile"]),
+            running_orders: gauge("running_orders", "Orders being downloaded", &["profile"]),
+            registry,
+        }
+    }
+}
+
+pub fn order_discovered(profile: &str, watch_dir: &str) {
+    METRICS
+        .orders_discovered
+        .with_label_values(&[profile, watch_dir])
+        .inc();
+}
+pub fn order_completed(profile: &str, watch_dir: &str) {
+    METRICS
+        .orders_completed
+        .with_label_values(&[profile, watch_dir])
+        .inc();
+}
+pub fn order_failed(profile: &str, watch_dir: &str) {
+    METRICS
+        .orders_failed
+        .with_label_values(&[profile, watch_dir])
+        .inc();
+}
+pub fn file_downloaded(profile: &str, watch_dir: &str, bytes: u64) {
+    METRICS
+        .files_downloaded
+        .with_label_values(&[profile, watch_dir])
+        .inc();
+    METRICS
+        .bytes_downloaded
+        .with_label_values(&[profile, watch_dir])
+        .inc_by(bytes);
+}
+pub fn file_uploaded(profile: &str, upload_dir: &str, bytes: u64) {
+    METRICS
+        .files_uploaded
+        .with_label_values(&[profile, upload_dir])
+        .inc();
+    METRICS
+        .bytes_uploaded
+        .with_label_values(&[profile, upload_dir])
+        .inc_by(bytes);
+}
+pub fn upload_completed(profile: &str, upload_dir: &str) {
+    METRICS
+        .uploads_completed
+        .with_label_values(&[profile, upload_dir])
+        .inc();
+}
+pub fn upload_failed(profile: &str, upload_dir: &str) {
+    METRICS
+        .uploads_failed
+        .with_label_values(&[profile, upload_dir])
+        .inc();
+}
+pub fn remote_deleted(profile: &str, watch_dir: &str) {
+    METRICS
+        .remote_deleted
+        .with_label_values(&[profile, watch_dir])
+        .inc();
+}
+pub fn scan_finished(profile: &str, watch_dir: &str, duration: Duration, ok: bool) {
+    METRICS
+        .scan_duration
+        .with_label_values(&[profile, watch_dir])
+        .observe(duration.as_secs_f64());
+    if !ok {
+        METRICS
+            .scan_errors
+            .with_label_values(&[profile, watch_dir])
+            .inc();
+    }
+}
+pub fn command(profile: &str, command: &str, duration: Duration) {
+    METRICS
+        .command_duration
+        .with_label_values(&[profile, command])
+        .observe(duration.as_secs_f64());
+}
+pub fn reconnect(profile: &str, success: bool) {
+    let result = if success { "success" } else { "failure" };
+    METRICS
+        .reconnects
+        .with_label_values(&[profile, result])
+        .inc();
+}
+pub fn checkout_exhausted(profile: &str, role: ConnectionRole) {
+    METRICS
+        .checkout_exhausted
+        .with_label_values(&[profile, role_label(role)])
+        .inc();
+}
+
+///Set gauges of the profile from a status snapshot
+pub fn set_status(profile: &str, status: &Status) {
+    for role in [ConnectionRole::Control, ConnectionRole::Transfer] {
+        for state in [
+            ConnectionState::Idle,
+            ConnectionState::Busy,
+            ConnectionState::Degraded,
+        ] {
+            let count = status
+                .connections
+                .iter()
+                .filter(|c| c.role == role && c.state == state)
+                .count();
+            METRICS
+                .connections
+                .with_label_values(&[profile, role_label(role), state_label(state)])
+                .set(count as i64);
+        }
+    }
+    METRICS
+        .queue_depth
+        .with_label_values(&[profile])
+        .set(status.pending.len() as i64);
+    METRICS
+        .running_orders
+        .with_label_values(&[profile])
+        .set(status.running.len() as i64);
+}
+fn role_label(role: ConnectionRole) -> &'static str {
+    match role {
+        ConnectionRole::Control => "control",
+        ConnectionRole::Transfer => "transfer",
+    }
+}
+fn state_label(state: ConnectionState) -> &'static str {
+    match state {
+        ConnectionState::Idle => "idle",
+        ConnectionState::Busy => "busy",
+        ConnectionState::Degraded => "degraded",
+    }
+}
+
+///Every metric in Prometheus text format
+pub fn encode() -> String {
+    TextEncoder::new()
+        .encode_to_string(&METRICS.registry.gather())
+        .unwrap_or_default()
+}
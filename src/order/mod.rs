@@ -1,6 +1,52 @@
-use crate::{utils, Connection, CustomError, FtpDirEntry, Result};
-use std::{cmp::Ordering, path::PathBuf};
-use tokio::sync::MutexGuard;
+use crate::{
+    state::{self, FileList},
+    utils, Connection, CustomError, EntrySource, FtpDirEntry, ListedEntry, Manifest, OrderProgress,
+    ReadinessContext, ReadinessStrategy, Result, RetryPolicy, SyncMode, UploadDir, WatchDir,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    cmp::Ordering,
+    collections::{HashMap, HashSet, VecDeque},
+    future::Future,
+    ops::DerefMut,
+    path::PathBuf,
+    time::Instant,
+};
+use tokio::sync::{MutexGuard, Semaphore};
+
+/// Lifecycle of an order:
+/// Discovered => Ready => Queued => Downloading => Verifying => CleaningUp => Done;
+/// Any failure => Failed (queued again after retry backoff) or DeadLettered once retries are exhausted
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OrderState {
+    Discovered,
+    Ready,
+    Queued,
+    Downloading,
+    Verifying,
+    CleaningUp,
+    Done,
+    Failed,
+    DeadLettered,
+}
+
+/// Connection of a download worker, given back to the pool as soon as the worker is done
+pub type WorkerConnection<'a> = Box<dyn DerefMut<Target = Connection> + Send + 'a>;
+
+/// Transfer in progress; dropped halfway (order cancelled) it marks the connection degraded,
+/// as the aborted transfer leaves replies pending on its control channel
+struct Transfer<'a> {
+    conn: &'a mut Connection,
+    done: bool,
+}
+impl Drop for Transfer<'_> {
+    fn drop(&mut self) {
+        if !self.done {
+            self.conn.set_degraded(true);
+        }
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct Order {
@@ -8,6 +54,27 @@ pub struct Order {
     download_target_path: PathBuf,
     files: Option<Vec<FtpDirEntry>>,
     folders: Option<Vec<FtpDirEntry>>,
+    ///Files rejected by the watched folder filter
+    excluded: Vec<FtpDirEntry>,
+    manifest: Option<Manifest>,
+    watch_dir: WatchDir,
+    state: OrderState,
+    attempts: u32,
+    last_error: Option<String>,
+    retry_at: Option<Instant>,
+    ///Unix seconds of the oldest file modification reported by the listing
+    oldest_modified: Option<i64>,
+    ///Listing date (unix seconds) of every file whose date the listing had in a known format
+    modified: HashMap<String, i64>,
+    ///Order is a single file, not a folder
+    file_order: bool,
+    ///Remote entries already deleted or archived, skipped when cleanup is retried
+    cleaned: HashSet<String>,
+    ///Remote data is never deleted or archived, whatever the sync mode
+    keep_remote: bool,
+    ///Order passed its checks and was journaled for download;
+    /// failed orders that were not are checked again on retry instead of being queued
+    accepted: bool,
 }
 
 impl Ord for Order {
@@ -31,20 +98,95 @@ impl PartialEq for Order {
     }
 }
 impl Order {
-    pub fn new(dir: &FtpDirEntry, local_dest: &str) -> Self {
+    ///Upload order: local folder `dir` is sent to `remote_dest` folder
+    pub fn for_upload(dir: &FtpDirEntry, remote_dest: &str, upload_dir: &UploadDir) -> Self {
+        Self::new(dir, remote_dest, &WatchDir::new(&upload_dir.local_path))
+    }
+    pub fn new(dir: &FtpDirEntry, local_dest: &str, watch_dir: &WatchDir) -> Self {
         let root_path = std::path::Path::new(&dir.get_full_path()).to_path_buf();
         let download_target_path = std::path::Path::new(local_dest).to_path_buf();
+        //single file is its own and only entry
+        let file_order = !dir.is_dir();
+        let (files, folders) = match file_order {
+            true => (Some(vec![dir.to_owned()]), Some(Vec::new())),
+            false => (None, None),
+        };
 
         Self {
             root_path,
             download_target_path,
-            files: None,
-            folders: None,
+            files,
+            folders,
+            excluded: Vec::new(),
+            manifest: None,
+            watch_dir: watch_dir.to_owned(),
+            state: OrderState::Discovered,
+            attempts: 0,
+            last_error: None,
+            retry_at: None,
+            oldest_modified: None,
+            modified: HashMap::new(),
+            file_order,
+            cleaned: HashSet::new(),
+            keep_remote: false,
+            accepted: false,
         }
     }
+    pub fn is_file_order(&self) -> bool {
+        self.file_order
+    }
+    pub fn get_state(&self) -> OrderState {
+        self.state
+    }
+    pub fn set_state(&mut self, state: OrderState) {
+        self.state = state;
+    }
+    ///Failed attempts so far
+    pub fn get_attempts(&self) -> u32 {
+        self.attempts
+    }
+    pub fn get_last_error(&self) -> Option<&str> {
+        self.last_error.as_deref()
+    }
+    ///Register failed attempt: schedule retry or dead-letter the order when retries are exhausted
+    pub fn fail(&mut self, error: String, policy: &RetryPolicy) -> OrderState {
+        self.attempts += 1;
+        self.last_error = Some(error);
+        if self.attempts >= policy.max_attempts {
+            self.state = OrderState::DeadLettered;
+            self.retry_at = None;
+        } else {
+            self.state = OrderState::Failed;
+            self.retry_at = Some(Instant::now() + policy.delay(self.attempts));
+        }
+        self.state
+    }
+    ///Restore failure history, e.g. when resuming order from journal
+    pub fn set_failures(&mut self, attempts: u32, last_error: Option<String>) {
+        self.attempts = attempts;
+        self.last_error = last_error;
+    }
+    ///Whether failed order waited long enough to be queued again
+    pub fn is_retry_due(&self) -> bool {
+        self.state == OrderState::Failed && self.retry_at.is_none_or(|at| Instant::now() >= at)
+    }
+    ///Give dead-lettered order a fresh set of attempts
+    pub fn requeue(&mut self) {
+        self.attempts = 0;
+        self.retry_at = None;
+        self.state = OrderState::Queued;
+    }
     pub async fn download(
         &self,
         conn: &mut MutexGuard<'_, Connection>,
+    ) -> Result<Vec<FtpDirEntry>> {
+        self.download_with(conn, |_| {}).await
+    }
+    ///Same as `download`, calling `on_downloaded` after every file present locally
+    pub async fn download_with(
+        &self,
+        conn: &mut MutexGuard<'_, Connection>,
+        on_downloaded: impl FnMut(&FtpDirEntry) + Send,
     ) -> Result<Vec<FtpDirEntry>> {
         if self.files.is_none() {
             return Err(CustomError::Io(
@@ -53,9 +195,155 @@ impl Order {
         }
         let files = self.get_files_list().unwrap();
         let dest = self.download_target_path.to_str().unwrap();
-        conn.batch_download(files, dest).await
+        conn.batch_download_with(files, dest, on_downloaded).await
+    }
+    ///Download order over all provided connections at once;
+    /// Workers take files from a shared queue (largest first), so a worker done with small files
+    /// picks up what is left instead of idling behind a slow one;
+    /// Every file transfer holds a permit of `slots`, which caps transfers over all orders;
+    /// A worker stops after its first failure (its connection is likely broken), remaining files go to others;
+    /// `on_downloaded` is awaited after every file present locally, its error fails the file;
+    /// Every received chunk is counted in `progress`;
+    /// Succeeds only when every file of the order is present locally;
+    /// Every connection is released as soon as its worker is done
+    pub async fn download_parallel<F, Fut>(
+        &self,
+        conns: Vec<WorkerConnection<'_>>,
+        slots: &Semaphore,
+        progress: &OrderProgress,
+        on_downloaded: F,
+    ) -> Result<Vec<FtpDirEntry>>
+    where
+        F: Fn(FtpDirEntry) -> Fut + Sync,
+        Fut: Future<Output = Result<()>> + Send,
+    {
+        let files = match self.get_files_list() {
+            Some(files) => files,
+            None => {
+                return Err(CustomError::Io(
+                    "job was not initialized properly (file list is None)".into(),
+                ))
+            }
+        };
+        let dest = self.download_target_path.to_str().unwrap();
+        let mut sorted = files.clone();
+        sorted.sort_by_key(|f| std::cmp::Reverse(f.get_size()));
+        let queue = std::sync::Mutex::new(VecDeque::from(sorted));
+        let failed = std::sync::Mutex::new(Vec::new());
+        let next = || {
+            queue
+                .lock()
+                .expect("download queue lock poisoned")
+                .pop_front()
+        };
+
+        let workers = conns.into_iter().map(|conn| async {
+            let mut conn = conn;
+            while let Some(file) = next() {
+                let permit = slots.acquire().await;
+                let path = file.get_full_path();
+                progress.file_started(&path, file.get_size());
+                let mut transfer = Transfer {
+                    conn: &mut conn,
+                    done: false,
+                };
+                let fetched = transfer
+                    .conn
+                    .fetch_file_with(&file, self.get_modified(&path), dest, |bytes| {
+                        progress.advance(&path, bytes)
+                    })
+                    .await;
+                transfer.done = true;
+                drop(transfer);
+                drop(permit);
+                match fetched {
+                    Ok(()) => {
+                        progress.file_done(&path, file.get_size());
+                        if let Err(e) = on_downloaded(file.to_owned()).await {
+                            failed
+                                .lock()
+                                .expect("download queue lock poisoned")
+                                .push((file, e));
+                        }
+                    }
+                    Err(e) => {
+                        progress.file_failed(&path);
+                        failed
+                            .lock()
+                            .expect("download queue lock poisoned")
+                            .push((file, e));
+                        break;
+                    }
+                }
+            }
+            //back to the pool while other workers are still busy
+            drop(conn);
+        });
+        futures::future::join_all(workers).await;
+
+        let failed = failed.into_inner().expect("download queue lock poisoned");
+        let left = queue.into_inner().expect("download queue lock poisoned");
+        if !failed.is_empty() || !left.is_empty() {
+            return Err(CustomError::Ftp(format!(
+                "failed to download some files: {:?}, not attempted: {:?}",
+                failed, left
+            )));
+        }
+        Ok(files)
     }
 
+    ///Watched folder the order was found in
+    pub fn get_watch_dir(&self) -> &WatchDir {
+        &self.watch_dir
+    }
+    pub fn get_sync_mode(&self) -> SyncMode {
+        self.watch_dir.sync_mode
+    }
+    ///Manifest the downloaded files must match before remote data is deleted
+    pub fn set_manifest(&mut self, manifest: Option<Manifest>) {
+        self.manifest = manifest;
+    }
+    pub fn get_manifest(&self) -> Option<&Manifest> {
+        self.manifest.as_ref()
+    }
+    ///Destination folder: local folder of download orders, remote folder of upload orders
+    pub fn get_target_path(&self) -> String {
+        self.download_target_path
+            .to_str()
+            .expect("cannot parse PathBuf to string")
+            .to_string()
+    }
+    ///Local folder the order root is downloaded to
+    pub fn get_local_root_path(&self) -> String {
+        self.get_local_path(&self.get_root_path())
+    }
+    ///Local path the remote file of this order is downloaded to
+    pub fn get_local_path(&self, remote_path: &str) -> String {
+        utils::get_local_path(remote_path, self.download_target_path.to_str().unwrap())
+    }
+    ///Verify downloaded files against the manifest, if the order has one;
+    /// files left out by the filter are not expected locally
+    pub async fn verify_downloaded(&self) -> Result<()> {
+        let manifest = match &self.manifest {
+            Some(manifest) => manifest,
+            None => return Ok(()),
+        };
+        let prefix = format!("{}/", self.get_root_path());
+        let excluded: HashSet<String> = self
+            .excluded
+            .iter()
+            .filter_map(|f| f.get_full_path().strip_prefix(&prefix).map(str::to_owned))
+            .collect();
+        let expected = Manifest {
+            files: manifest
+                .files
+                .iter()
+                .filter(|entry| !excluded.contains(&entry.name))
+                .cloned()
+                .collect(),
+        };
+        expected.verify_local(&self.get_local_root_path()).await
+    }
     pub fn get_root_path(&self) -> String {
         self.root_path
             .to_owned()
@@ -65,18 +353,29 @@ impl Order {
     }
 
     pub async fn read_all_entries(&mut self, conn: &mut MutexGuard<'_, Connection>) -> Result<()> {
-        let mut entries = conn.get_dir_entries(&self.get_root_path()).await?;
+        let source: &mut Connection = conn;
+        self.read_all_entries_from(source).await
+    }
+    ///Walk the order tree of any source (remote ftp or local folder)
+    pub async fn read_all_entries_from(&mut self, source: &mut dyn EntrySource) -> Result<()> {
+        if self.file_order {
+            return Ok(());
+        }
+        self.oldest_modified = None;
+        self.modified.clear();
+        let mut listed = source.list(&self.get_root_path()).await?;
+        let mut entries = self.take_entries(&mut listed);
         let (mut folders, mut files) = utils::categorize_entries(&mut entries);
         let mut output_folders = folders.clone();
         output_folders.push(FtpDirEntry::Folder(self.get_root_path()));
 
-        while !folders.is_empty() {
-            let folder = folders.pop().unwrap(); //unwrap is safe as folders was not empty
+        while let Some(folder) = folders.pop() {
             let path = match folder {
                 FtpDirEntry::Folder(path) => path,
                 _ => unreachable!(),
             };
-            entries = conn.get_dir_entries(&path).await?;
+            listed = source.list(&path).await?;
+            entries = self.take_entries(&mut listed);
             let (mut fol, mut fil) = utils::categorize_entries(&mut entries);
             let mut cloned_fol = fol.clone();
             folders.append(&mut fol);
@@ -85,33 +384,142 @@ impl Order {
         }
 
         output_folders.sort();
+        let order_path = self.get_root_path();
+        let filter = &self.watch_dir.filter;
+        let (files, excluded) = files.into_iter().partition(|f| match f {
+            FtpDirEntry::File(path, size) => filter.accepts(&order_path, path, *size),
+            FtpDirEntry::Folder(..) => true,
+        });
         self.folders = Some(output_folders);
         self.files = Some(files);
+        self.excluded = excluded;
 
         Ok(())
     }
+    ///Strip listing details off entries, remembering file modification dates
+    fn take_entries(&mut self, listed: &mut Vec<ListedEntry>) -> Vec<FtpDirEntry> {
+        listed
+            .drain(..)
+            .map(|listed| {
+                self.note_modified(&listed);
+                listed.entry
+            })
+            .collect()
+    }
+    ///Remember listing date of an order file, e.g. of a single-file order from its folder listing
+    pub fn note_modified(&mut self, listed: &ListedEntry) {
+        if listed.entry.is_dir() {
+            return;
+        }
+        if let Some(modified) = utils::parse_listing_date(&listed.modified) {
+            self.oldest_modified = Some(self.oldest_modified.map_or(modified, |m| m.min(modified)));
+            self.modified.insert(listed.entry.get_full_path(), modified);
+        }
+    }
+    ///Listing date of the order file, unix seconds
+    pub fn get_modified(&self, path: &str) -> Option<i64> {
+        self.modified.get(path).copied()
+    }
+    ///Files of the order with their listing dates
+    pub fn dated_files(&self) -> FileList {
+        state::dated_file_list(&self.get_files_list().unwrap_or_default(), |path| {
+            self.get_modified(path)
+        })
+    }
+    ///Unix seconds of the oldest file of the order, known after `read_all_entries`
+    pub fn get_oldest_modified(&self) -> Option<i64> {
+        self.oldest_modified
+    }
+    ///Total size of order files in bytes
+    pub fn get_total_size(&self) -> usize {
+        self.files
+            .as_ref()
+            .map_or(0, |files| files.iter().map(|f| f.get_size()).sum())
+    }
+    ///Set file and folder lists directly, e.g. when resuming order from journal
+    pub fn set_entries(&mut self, files: Vec<FtpDirEntry>, folders: Vec<FtpDirEntry>) {
+        self.files = Some(files);
+        self.folders = Some(folders);
+    }
+    pub fn set_modified(&mut self, modified: HashMap<String, i64>) {
+        self.modified = modified;
+    }
+    pub fn set_excluded(&mut self, excluded: Vec<FtpDirEntry>) {
+        self.excluded = excluded;
+    }
+    pub fn set_cleaned(&mut self, cleaned: HashSet<String>) {
+        self.cleaned = cleaned;
+    }
+    ///Remote entry was deleted or archived
+    pub fn mark_cleaned(&mut self, path: String) {
+        self.cleaned.insert(path);
+    }
+    pub fn is_cleaned(&self, path: &str) -> bool {
+        self.cleaned.contains(path)
+    }
+    pub fn set_keep_remote(&mut self, keep_remote: bool) {
+        self.keep_remote = keep_remote;
+    }
+    pub fn keeps_remote(&self) -> bool {
+        self.keep_remote
+    }
+    pub fn set_accepted(&mut self) {
+        self.accepted = true;
+    }
+    pub fn is_accepted(&self) -> bool {
+        self.accepted
+    }
+    ///Files left out by the watched folder filter, known after `read_all_entries`
+    pub fn get_excluded_list(&self) -> &[FtpDirEntry] {
+        &self.excluded
+    }
     pub fn get_files_list(&self) -> Option<Vec<FtpDirEntry>> {
         self.files.to_owned()
     }
     pub fn get_folders_list(&self) -> Option<Vec<FtpDirEntry>> {
         self.folders.to_owned()
     }
-    pub async fn is_ready_for_download(&self, conn: &mut MutexGuard<'_, Connection>) -> bool {
-        let ready_flag_file_ext = &conn.get_ready_flag().to_owned();
-        if let Ok(entries) = conn.get_dir_entries(&self.get_root_path()).await {
-            entries.iter().any(|ent| {
-                !ent.is_dir()
-                    && ent
-                        .get_full_path()
-                        .chars()
-                        .rev()
-                        .take(3)
-                        .collect::<String>()
-                        .to_lowercase()
-                        == *ready_flag_file_ext
-            })
-        } else {
-            false
-        }
+    ///Ask readiness strategy whether the order folder finished being written;
+    /// Errors (listing, reading or parsing flag files and manifests) mean "not ready yet", callers report them
+    pub async fn is_ready_for_download(
+        &self,
+        conn: &mut MutexGuard<'_, Connection>,
+        strategy: &dyn ReadinessStrategy,
+    ) -> Result<bool> {
+        let source: &mut Connection = conn;
+        self.is_ready(source, strategy).await
+    }
+    ///Same as `is_ready_for_download` for any source (remote ftp or local folder)
+    pub async fn is_ready(
+        &self,
+        source: &mut dyn EntrySource,
+        strategy: &dyn ReadinessStrategy,
+    ) -> Result<bool> {
+        //single-file order is judged by the listing of its folder
+        let siblings = match self.file_order {
+            true => match self.root_path.parent().and_then(|p| p.to_str()) {
+                Some(parent) => source.list(parent).await?,
+                None => Vec::new(),
+            },
+            false => Vec::new(),
+        };
+        self.is_ready_among(source, strategy, siblings).await
+    }
+    ///Same as `is_ready` when listing of the order's parent folder is at hand already (e.g. from scan)
+    pub async fn is_ready_among(
+        &self,
+        source: &mut dyn EntrySource,
+        strategy: &dyn ReadinessStrategy,
+        siblings: Vec<ListedEntry>,
+    ) -> Result<bool> {
+        let root_path = self.get_root_path();
+        let mut ctx = match self.file_order {
+            true => ReadinessContext::for_file(&root_path, siblings, source),
+            false => ReadinessContext {
+                siblings,
+                ..ReadinessContext::new(&root_path, source).await?
+            },
+        };
+        strategy.is_ready(&mut ctx).await
     }
 }
@@ -1,55 +1,325 @@
+use crate::breaker::{CircuitBreaker, CircuitState};
+use crate::connection::Activity;
+use crate::events::{self, Event, EventStream};
+use crate::metrics;
+use crate::AuditLog;
 use crate::Config;
 use crate::Connection;
 use crate::CustomError;
 use crate::Result;
-use tokio::sync::{Mutex, MutexGuard};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tokio::sync::{broadcast, Mutex, MutexGuard};
+use tracing::{error, info, warn};
 
 const CONN_RETRY_ATTEMPTS: i32 = 5;
+
+/// Role a pooled connection is reserved for.
+/// Control connections serve remote scans and readiness checks,
+/// transfer connections serve downloads, so a large backlog never blocks scanning
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConnectionRole {
+    Control,
+    Transfer,
+}
+
 #[derive(Debug)]
 pub struct Pool {
-    inner: Vec<Mutex<Connection>>,
+    control: Vec<Mutex<Connection>>,
+    transfer: Vec<Mutex<Connection>>,
+    breaker: std::sync::Mutex<CircuitBreaker>,
+    events: broadcast::Sender<Event>,
+    config: std::sync::RwLock<Config>,
+    auth_failure: std::sync::Mutex<Option<String>>,
+    ///Activity of every connection (id, role), in `get_connections` order
+    activity: Vec<(u64, ConnectionRole, Arc<Activity>)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConnectionState {
+    Idle,
+    Busy,
+    Degraded,
+}
+
+/// What a pool connection is doing right now
+#[derive(Debug, Clone, Serialize)]
+pub struct ConnectionStatus {
+    pub id: u64,
+    pub role: ConnectionRole,
+    pub state: ConnectionState,
+    ///Last command started by a busy or degraded connection
+    pub activity: Option<String>,
 }
 impl Pool {
     pub async fn with_config(config: &Config) -> Result<Self> {
-        let size = config.connections;
+        let audit = match &config.audit {
+            Some(audit) => Some(Arc::new(AuditLog::open(audit)?)),
+            None => None,
+        };
+        let transfer = Pool::connect(config, config.connections, &audit).await?;
+        let control = Pool::connect(config, config.scan_connections, &audit).await?;
+        //if transfer.is_empty() { return Err(CustomError::Ftp("cannot establish connection".into()))}
+        let breaker = std::sync::Mutex::new(CircuitBreaker::new(&config.reconnect));
+        let (events, _) = broadcast::channel(config.event_buffer.max(1));
+        //freshly opened connections are all free
+        let activity = control
+            .iter()
+            .map(|c| (ConnectionRole::Control, c))
+            .chain(transfer.iter().map(|c| (ConnectionRole::Transfer, c)))
+            .filter_map(|(role, mx)| {
+                let conn = mx.try_lock().ok()?;
+                Some((conn.get_id(), role, conn.get_activity()))
+            })
+            .collect();
+        Ok(Self {
+            activity,
+            control,
+            transfer,
+            breaker,
+            events,
+            config: std::sync::RwLock::new(config.to_owned()),
+            auth_failure: std::sync::Mutex::new(None),
+        })
+    }
+    ///Open up to `size` connections, giving up on the rest after too many failed attempts;
+    /// Rejected credentials abort immediately to avoid locking the account
+    async fn connect(
+        config: &Config,
+        size: usize,
+        audit: &Option<Arc<AuditLog>>,
+    ) -> Result<Vec<Mutex<Connection>>> {
         let mut retry_count = 0;
         let mut inner = Vec::with_capacity(size);
 
         while inner.len() < size {
-            if let Ok(conn) = Connection::with_config(&config).await {
-                inner.push(Mutex::new(conn));
-            } else {
-                retry_count += 1;
+            match Connection::with_config(config).await {
+                Ok(mut conn) => {
+                    conn.set_audit(audit.clone());
+                    inner.push(Mutex::new(conn))
+                }
+                Err(e) if e.is_auth() => return Err(e),
+                Err(_) => retry_count += 1,
             }
             if !inner.is_empty() && retry_count > CONN_RETRY_ATTEMPTS {
                 break;
             }
         }
-        //if inner.is_empty() { return Err(CustomError::Ftp("cannot establish connection".into()))}
-        Ok(Self { inner })
+        Ok(inner)
     }
 
+    ///Get free transfer connection
     pub async fn get_free_connection(&'static self) -> Result<MutexGuard<'static, Connection>> {
+        self.get_free_connection_for(ConnectionRole::Transfer).await
+    }
+    ///Get free connection reserved for the provided role;
+    /// If no control connections are configured, control work shares transfer connections
+    pub async fn get_free_connection_for(
+        &'static self,
+        role: ConnectionRole,
+    ) -> Result<MutexGuard<'static, Connection>> {
         //iterate Vec<Mutex<Connection>> in a loop trying to aquire lock on each mutex
         //if lock is aquired => connection is free and can be used => return this Connection
         //return Err if tried too many times
+        if !self.with_breaker(|b| b.allows_checkout()) {
+            return Err(CustomError::Ftp(
+                "circuit is open: ftp server considered unavailable".into(),
+            ));
+        }
+        let slots = self.get_connections_for(role);
         let mut count = 0;
         loop {
-            for mx in (*self.inner).iter() {
+            //lock every free connection and keep the healthiest one;
+            // degraded ones wait for the controller, they may have replies pending
+            let mut best: Option<MutexGuard<'static, Connection>> = None;
+            for mx in slots.iter() {
                 if let Ok(lock) = mx.try_lock() {
-                    return Ok(lock);
+                    if lock.is_degraded() {
+                        continue;
+                    }
+                    if best
+                        .as_ref()
+                        .is_none_or(|b| lock.health_score() > b.health_score())
+                    {
+                        best = Some(lock);
+                    }
                 }
             }
+            if let Some(lock) = best {
+                return Ok(lock);
+            }
             count += 1;
             if count > CONN_RETRY_ATTEMPTS {
-                return Err(CustomError::Ftp("all connections are busy".into()));
+                metrics::checkout_exhausted(&self.get_config().get_profile(), role);
+                return Err(CustomError::Ftp(format!(
+                    "all {:?} connections are busy",
+                    role
+                )));
             }
         }
     }
+    ///Lock up to `max` free healthy connections of the role without waiting;
+    /// Used to spread a single order across idle connections
+    pub fn try_get_free_connections(
+        &'static self,
+        role: ConnectionRole,
+        max: usize,
+    ) -> Vec<MutexGuard<'static, Connection>> {
+        if max == 0 || !self.with_breaker(|b| b.allows_checkout()) {
+            return Vec::new();
+        }
+        self.get_connections_for(role)
+            .iter()
+            .filter_map(|mx| mx.try_lock().ok())
+            .filter(|conn| !conn.is_degraded())
+            .take(max)
+            .collect()
+    }
+    ///Subscribe to engine events as a stream, see `events::stream`
+    pub fn events(&self) -> EventStream {
+        events::stream(self.events.subscribe())
+    }
+    ///Subscribe to engine events: pool, connections, scans and orders
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.events.subscribe()
+    }
+    pub fn emit(&self, event: Event) {
+        //no subscribers is not an error
+        self.events.send(event).ok();
+    }
+    pub fn circuit_state(&self) -> CircuitState {
+        self.with_breaker(|b| b.state())
+    }
+    ///Whether controller may try to reconnect now (one probe at a time when half-open);
+    /// Never while the server rejects current credentials
+    pub fn allows_reconnect(&self) -> bool {
+        self.get_auth_failure().is_none() && self.with_breaker(|b| b.allows_reconnect())
+    }
+    ///Current profile config; connections pick it up on their next reconnect
+    pub fn get_config(&self) -> Config {
+        self.config
+            .read()
+            .expect("pool config lock poisoned")
+            .to_owned()
+    }
+    ///Replace profile config (e.g. after re-reading config file);
+    /// Changed credentials lift the authentication failure stop
+    pub fn reload_config(&self, config: Config) {
+        let mut current = self.config.write().expect("pool config lock poisoned");
+        if !current.same_credentials(&config) {
+            let was_failed = self
+                .auth_failure
+                .lock()
+                .expect("auth failure lock poisoned")
+                .take()
+                .is_some();
+            if was_failed {
+                info!("credentials changed, resuming reconnects");
+            }
+            self.emit(Event::CredentialsChanged {
+                host: config.get_hostname(),
+                user: config.user.to_owned(),
+            });
+        }
+        *current = config;
+    }
+    ///Server rejected credentials: stop reconnecting until they are changed;
+    /// A half-open probe is released, so reconnects resume once credentials change
+    pub fn record_auth_failure(&self, message: String) {
+        self.with_breaker(|b| b.cancel_probe());
+        let mut failure = self
+            .auth_failure
+            .lock()
+            .expect("auth failure lock poisoned");
+        if failure.is_some() {
+            return;
+        }
+        *failure = Some(message.to_owned());
+        drop(failure);
+        let config = self.get_config();
+        error!(
+            user = %config.user,
+            host = %config.get_hostname(),
+            message = %message,
+            "authentication failed, reconnects stopped"
+        );
+        self.emit(Event::AuthenticationFailed {
+            host: config.get_hostname(),
+            user: config.user,
+            message,
+        });
+    }
+    ///Reason the server rejected current credentials, if it did
+    pub fn get_auth_failure(&self) -> Option<String> {
+        self.auth_failure
+            .lock()
+            .expect("auth failure lock poisoned")
+            .to_owned()
+    }
+    ///Feed result of reconnect attempt to the circuit breaker
+    pub fn record_reconnect(&self, success: bool) {
+        self.with_breaker(|b| {
+            if success {
+                b.record_success()
+            } else {
+                b.record_failure()
+            }
+        })
+    }
+    ///Run closure over the breaker, emitting event if it reports a state change
+    fn with_breaker<T>(&self, f: impl FnOnce(&mut CircuitBreaker) -> T) -> T {
+        let (res, change) = {
+            let mut breaker = self.breaker.lock().expect("circuit breaker lock poisoned");
+            let res = f(&mut breaker);
+            (res, breaker.take_change())
+        };
+        if let Some((from, to)) = change {
+            warn!(?from, ?to, "connection pool circuit changed");
+            self.emit(Event::CircuitStateChanged { from, to });
+        }
+        res
+    }
+    ///State of every connection, without waiting for busy ones
+    pub fn connection_status(&self) -> Vec<ConnectionStatus> {
+        self.get_connections()
+            .zip(self.activity.iter())
+            .map(|(mx, (id, role, activity))| {
+                let state = if activity.is_degraded() {
+                    ConnectionState::Degraded
+                } else if mx.try_lock().is_err() {
+                    ConnectionState::Busy
+                } else {
+                    ConnectionState::Idle
+                };
+                ConnectionStatus {
+                    id: *id,
+                    role: *role,
+                    state,
+                    activity: match state {
+                        ConnectionState::Idle => None,
+                        _ => activity.current(),
+                    },
+                }
+            })
+            .collect()
+    }
     pub fn len(&self) -> usize {
-        self.inner.len()
+        self.control.len() + self.transfer.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
     }
-    pub fn get_connections(&self) -> &Vec<Mutex<Connection>> {
-        &self.inner
+    ///Iterate over all connections regardless of role
+    pub fn get_connections(&self) -> impl Iterator<Item = &Mutex<Connection>> {
+        self.control.iter().chain(self.transfer.iter())
+    }
+    ///Get connections reserved for the provided role
+    pub fn get_connections_for(&self, role: ConnectionRole) -> &Vec<Mutex<Connection>> {
+        match role {
+            ConnectionRole::Control if !self.control.is_empty() => &self.control,
+            _ => &self.transfer,
+        }
     }
 }
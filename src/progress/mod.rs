@@ -0,0 +1,148 @@
+use crate::FtpDirEntry;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Instant;
+
+/// Progress of a file being downloaded
+#[derive(Debug, Clone, Serialize)]
+pub struct FileProgress {
+    pub path: String,
+    pub bytes_done: u64,
+    pub bytes_total: u64,
+    pub bytes_per_sec: u64,
+}
+
+/// Progress of an order; files already present locally count as done but not into throughput
+#[derive(Debug, Clone, Serialize)]
+pub struct Progress {
+    pub order: String,
+    pub files_done: usize,
+    pub files_total: usize,
+    pub bytes_done: u64,
+    pub bytes_total: u64,
+    ///Average since the download started
+    pub bytes_per_sec: u64,
+    ///None until something was transferred
+    pub eta_sec: Option<u64>,
+    pub elapsed_sec: u64,
+    ///Files being downloaded right now
+    pub files: Vec<FileProgress>,
+}
+
+#[derive(Debug)]
+struct FileState {
+    done: u64,
+    total: u64,
+    started: Instant,
+}
+
+#[derive(Debug)]
+struct Counters {
+    files_done: usize,
+    bytes_done: u64,
+    ///Bytes actually received, skipped files excluded
+    transferred: u64,
+    running: HashMap<String, FileState>,
+}
+
+/// Progress tracker of a running order, shared by its download workers
+#[derive(Debug)]
+pub struct OrderProgress {
+    order: String,
+    files_total: usize,
+    bytes_total: u64,
+    started: Instant,
+    counters: Mutex<Counters>,
+}
+
+impl OrderProgress {
+    pub fn new(order: &str, files: &[FtpDirEntry]) -> Self {
+        let files: Vec<&FtpDirEntry> = files.iter().filter(|f| !f.is_dir()).collect();
+        Self {
+            order: order.to_owned(),
+            files_total: files.len(),
+            bytes_total: files.iter().map(|f| f.get_size() as u64).sum(),
+            started: Instant::now(),
+            counters: Mutex::new(Counters {
+                files_done: 0,
+                bytes_done: 0,
+                transferred: 0,
+                running: HashMap::new(),
+            }),
+        }
+    }
+    pub fn file_started(&self, path: &str, total: usize) {
+        self.lock().running.insert(
+            path.to_owned(),
+            FileState {
+                done: 0,
+                total: total as u64,
+                started: Instant::now(),
+            },
+        );
+    }
+    ///`bytes` more of the file were received
+    pub fn advance(&self, path: &str, bytes: u64) {
+        let mut counters = self.lock();
+        counters.bytes_done += bytes;
+        counters.transferred += bytes;
+        if let Some(file) = counters.running.get_mut(path) {
+            file.done += bytes;
+        }
+    }
+    ///File is present locally, downloaded or skipped
+    pub fn file_done(&self, path: &str, total: usize) {
+        let mut counters = self.lock();
+        let done = counters.running.remove(path).map_or(0, |f| f.done);
+        counters.files_done += 1;
+        //skipped file, or server sent other size than listed
+        counters.bytes_done = (counters.bytes_done + total as u64).saturating_sub(done);
+    }
+    ///File failed, its bytes don't count anymore
+    pub fn file_failed(&self, path: &str) {
+        let mut counters = self.lock();
+        if let Some(file) = counters.running.remove(path) {
+            counters.bytes_done = counters.bytes_done.saturating_sub(file.done);
+        }
+    }
+    pub fn snapshot(&self) -> Progress {
+        let counters = self.lock();
+        let elapsed = self.started.elapsed().as_secs_f64();
+        let bytes_per_sec = rate(counters.transferred, elapsed);
+        let left = self.bytes_total.saturating_sub(counters.bytes_done);
+        let mut files: Vec<FileProgress> = counters
+            .running
+            .iter()
+            .map(|(path, f)| FileProgress {
+                path: path.to_owned(),
+                bytes_done: f.done,
+                bytes_total: f.total,
+                bytes_per_sec: rate(f.done, f.started.elapsed().as_secs_f64()),
+            })
+            .collect();
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+        Progress {
+            order: self.order.to_owned(),
+            files_done: counters.files_done,
+            files_total: self.files_total,
+            bytes_done: counters.bytes_done,
+            bytes_total: self.bytes_total,
+            bytes_per_sec,
+            eta_sec: (bytes_per_sec > 0).then(|| left.div_ceil(bytes_per_sec)),
+            elapsed_sec: elapsed as u64,
+            files,
+        }
+    }
+    fn lock(&self) -> std::sync::MutexGuard<'_, Counters> {
+        self.counters.lock().expect("progress lock poisoned")
+    }
+}
+
+fn rate(bytes: u64, secs: f64) -> u64 {
+    if secs > 0.0 {
+        (bytes as f64 / secs) as u64
+    } else {
+        0
+    }
+}
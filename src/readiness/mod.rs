@@ -0,0 +1,602 @@
+use crate::utils::{self, ListedEntry, NamePattern};
+use crate::{Connection, FtpDirEntry, Manifest, Result};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// State of an order that was not checked for this long is dropped (order downloaded or gone)
+const FORGET_AFTER: Duration = Duration::from_secs(600);
+
+/// Anything an order can be listed and read from (remote ftp or local folder)
+#[async_trait]
+pub trait EntrySource: Send {
+    ///List direct children of the folder
+    async fn list(&mut self, path: &str) -> Result<Vec<ListedEntry>>;
+    ///Read whole file into memory
+    async fn read(&mut self, path: &str) -> Result<Vec<u8>>;
+}
+
+#[async_trait]
+impl EntrySource for Connection {
+    async fn list(&mut self, path: &str) -> Result<Vec<ListedEntry>> {
+        self.get_dir_listing(path).await
+    }
+    async fn read(&mut self, path: &str) -> Result<Vec<u8>> {
+        Ok(self.simple_retr(path).await?.into_inner())
+    }
+}
+
+/// Local file system as a source of orders (upload mode)
+#[derive(Debug, Default)]
+pub struct LocalFs;
+
+#[async_trait]
+impl EntrySource for LocalFs {
+    async fn list(&mut self, path: &str) -> Result<Vec<ListedEntry>> {
+        let mut output = Vec::new();
+        let mut dir = tokio::fs::read_dir(path).await?;
+        while let Some(entry) = dir.next_entry().await? {
+            let meta = entry.metadata().await?;
+            let full_path = format!("{}/{}", path, entry.file_name().to_string_lossy());
+            let entry = if meta.is_dir() {
+                FtpDirEntry::Folder(full_path)
+            } else {
+                FtpDirEntry::File(full_path, meta.len() as usize)
+            };
+            let modified = utils::modified_secs(&meta)
+                .map(|secs| secs.to_string())
+                .unwrap_or_default();
+            output.push(ListedEntry { entry, modified });
+        }
+        Ok(output)
+    }
+    async fn read(&mut self, path: &str) -> Result<Vec<u8>> {
+        Ok(tokio::fs::read(path).await?)
+    }
+}
+
+/// What a strategy gets to decide whether an order folder is complete
+pub struct ReadinessContext<'a> {
+    ///Full path of the order folder
+    pub order_path: &'a str,
+    ///Direct children of the order folder (single-file orders: the file itself)
+    pub entries: Vec<ListedEntry>,
+    ///Entries next to the order in its parent folder, when known
+    pub siblings: Vec<ListedEntry>,
+    pub source: &'a mut dyn EntrySource,
+}
+impl ReadinessContext<'_> {
+    pub async fn new<'a>(
+        order_path: &'a str,
+        source: &'a mut dyn EntrySource,
+    ) -> Result<ReadinessContext<'a>> {
+        let entries = source.list(order_path).await?;
+        Ok(ReadinessContext {
+            order_path,
+            entries,
+            siblings: Vec::new(),
+            source,
+        })
+    }
+    ///Context of a single-file order, built from listing of its parent folder
+    pub fn for_file<'a>(
+        order_path: &'a str,
+        siblings: Vec<ListedEntry>,
+        source: &'a mut dyn EntrySource,
+    ) -> ReadinessContext<'a> {
+        let entries = siblings
+            .iter()
+            .filter(|e| e.entry.get_full_path() == order_path)
+            .cloned()
+            .collect();
+        ReadinessContext {
+            order_path,
+            entries,
+            siblings,
+            source,
+        }
+    }
+    ///Names of files directly in the order folder
+    pub fn file_names(&self) -> impl Iterator<Item = String> + '_ {
+        self.entries
+            .iter()
+            .filter(|e| !e.entry.is_dir())
+            .map(|e| e.name())
+    }
+    ///List the whole order tree, folders first
+    pub async fn walk(&mut self) -> Result<Vec<ListedEntry>> {
+        let mut output = self.entries.clone();
+        let mut folders: Vec<String> = output
+            .iter()
+            .filter(|e| e.entry.is_dir())
+            .map(|e| e.entry.get_full_path())
+            .collect();
+        while let Some(folder) = folders.pop() {
+            for listed in self.source.list(&folder).await? {
+                if let FtpDirEntry::Folder(path) = &listed.entry {
+                    folders.push(path.to_owned());
+                }
+                output.push(listed);
+            }
+        }
+        Ok(output)
+    }
+}
+
+/// Decides whether an order folder finished being written and can be transferred
+#[async_trait]
+pub trait ReadinessStrategy: Send + Sync + fmt::Debug {
+    async fn is_ready(&self, ctx: &mut ReadinessContext<'_>) -> Result<bool>;
+    ///Manifest read while deciding the order is ready, used to verify downloaded files
+    fn take_manifest(&self, _order_path: &str) -> Option<Manifest> {
+        None
+    }
+}
+
+/// Ready when a file with exactly this name is present
+#[derive(Debug)]
+pub struct FlagFile(pub String);
+#[async_trait]
+impl ReadinessStrategy for FlagFile {
+    async fn is_ready(&self, ctx: &mut ReadinessContext<'_>) -> Result<bool> {
+        Ok(ctx.file_names().any(|name| name == self.0))
+    }
+}
+
+/// Ready when any file name matches the glob or regex
+#[derive(Debug)]
+pub struct FlagPattern(pub NamePattern);
+#[async_trait]
+impl ReadinessStrategy for FlagPattern {
+    async fn is_ready(&self, ctx: &mut ReadinessContext<'_>) -> Result<bool> {
+        Ok(ctx.file_names().any(|name| self.0.matches(&name)))
+    }
+}
+
+/// Ready when any file has this extension (any length, case-insensitive)
+#[derive(Debug)]
+pub struct FlagExtension(String);
+impl FlagExtension {
+    pub fn new(ext: &str) -> Self {
+        Self(ext.trim_start_matches('.').to_lowercase())
+    }
+}
+#[async_trait]
+impl ReadinessStrategy for FlagExtension {
+    async fn is_ready(&self, ctx: &mut ReadinessContext<'_>) -> Result<bool> {
+        Ok(ctx.file_names().any(|name| {
+            name.rsplit_once('.')
+                .is_some_and(|(_, ext)| ext.to_lowercase() == self.0)
+        }))
+    }
+}
+
+/// Ready when a companion file named after the order is present next to it,
+/// e.g. `data.csv` + `data.csv.done` or `order/` + `order.done`
+#[derive(Debug)]
+pub struct Companion(pub String);
+impl Companion {
+    ///Name of the companion file of the order
+    pub fn name_for(&self, order_path: &str) -> String {
+        format!("{}{}", utils::get_file_name(order_path), self.0)
+    }
+}
+#[async_trait]
+impl ReadinessStrategy for Companion {
+    async fn is_ready(&self, ctx: &mut ReadinessContext<'_>) -> Result<bool> {
+        let companion = self.name_for(ctx.order_path);
+        Ok(ctx
+            .siblings
+            .iter()
+            .any(|e| !e.entry.is_dir() && e.name() == companion))
+    }
+}
+
+/// Per-order state of stateful strategies; state is kept while the order is checked,
+/// so strategies combined by `All` keep counting while other members are not ready yet
+#[derive(Debug)]
+struct OrderStates<T>(Mutex<HashMap<String, (T, Instant)>>);
+impl<T> Default for OrderStates<T> {
+    fn default() -> Self {
+        Self(Mutex::new(HashMap::new()))
+    }
+}
+impl<T> OrderStates<T> {
+    ///Update state of the order, created by `init` if there is none; states of orders not
+    /// checked for `FORGET_AFTER` are dropped
+    fn update<R>(
+        &self,
+        order_path: &str,
+        init: impl FnOnce() -> T,
+        f: impl FnOnce(&mut T) -> R,
+    ) -> R {
+        let mut states = self.0.lock().expect("readiness state lock poisoned");
+        states.retain(|_, (_, checked)| checked.elapsed() < FORGET_AFTER);
+        let (state, checked) = states
+            .entry(order_path.to_owned())
+            .or_insert_with(|| (init(), Instant::now()));
+        *checked = Instant::now();
+        f(state)
+    }
+    fn insert(&self, order_path: &str, state: T) {
+        let mut states = self.0.lock().expect("readiness state lock poisoned");
+        states.retain(|_, (_, checked)| checked.elapsed() < FORGET_AFTER);
+        states.insert(order_path.to_owned(), (state, Instant::now()));
+    }
+    fn remove(&self, order_path: &str) -> Option<T> {
+        self.0
+            .lock()
+            .expect("readiness state lock poisoned")
+            .remove(order_path)
+            .map(|(state, _)| state)
+    }
+}
+
+/// (path, size, date) of every entry in the order tree
+type TreeSignature = Vec<(String, usize, String)>;
+
+/// Ready when names, sizes and dates of the whole order tree did not change for `scans` checks in a row
+#[derive(Debug)]
+pub struct Stable {
+    scans: u32,
+    seen: OrderStates<(TreeSignature, u32)>,
+}
+impl Stable {
+    pub fn new(scans: u32) -> Self {
+        Self {
+            scans,
+            seen: OrderStates::default(),
+        }
+    }
+}
+#[async_trait]
+impl ReadinessStrategy for Stable {
+    async fn is_ready(&self, ctx: &mut ReadinessContext<'_>) -> Result<bool> {
+        let mut signature: TreeSignature = ctx
+            .walk()
+            .await?
+            .into_iter()
+            .map(|listed| match listed.entry {
+                FtpDirEntry::File(path, size) => (path, size, listed.modified),
+                FtpDirEntry::Folder(path) => (path, 0, listed.modified),
+            })
+            .collect();
+        signature.sort();
+
+        let count = self.seen.update(
+            ctx.order_path,
+            || (Vec::new(), 0),
+            |(prev, count)| {
+                if *prev == signature {
+                    *count += 1;
+                } else {
+                    *prev = signature;
+                    *count = 1;
+                }
+                *count
+            },
+        );
+        Ok(count >= self.scans)
+    }
+}
+
+/// Ready when nothing in the order tree was modified for `age`, by the dates of the listing;
+/// Without any date the server understands, age counts from when the order was first seen
+#[derive(Debug)]
+pub struct MinAge {
+    age: Duration,
+    first_seen: OrderStates<Instant>,
+}
+impl MinAge {
+    pub fn new(age: Duration) -> Self {
+        Self {
+            age,
+            first_seen: OrderStates::default(),
+        }
+    }
+}
+#[async_trait]
+impl ReadinessStrategy for MinAge {
+    async fn is_ready(&self, ctx: &mut ReadinessContext<'_>) -> Result<bool> {
+        let newest = ctx
+            .walk()
+            .await?
+            .iter()
+            .filter_map(|listed| utils::parse_listing_date(&listed.modified))
+            .max();
+        let age = match newest {
+            Some(modified) => chrono::Utc::now().timestamp() - modified,
+            None => self.first_seen.update(ctx.order_path, Instant::now, |at| {
+                at.elapsed().as_secs() as i64
+            }),
+        };
+        Ok(age >= self.age.as_secs() as i64)
+    }
+}
+
+/// Ready when every inner strategy says so (all of them are evaluated)
+#[derive(Debug)]
+pub struct All(pub Vec<Arc<dyn ReadinessStrategy>>);
+#[async_trait]
+impl ReadinessStrategy for All {
+    async fn is_ready(&self, ctx: &mut ReadinessContext<'_>) -> Result<bool> {
+        //evaluate all, so stateful strategies keep counting
+        let mut ready = true;
+        for strategy in self.0.iter() {
+            ready &= strategy.is_ready(ctx).await?;
+        }
+        Ok(ready)
+    }
+    fn take_manifest(&self, order_path: &str) -> Option<Manifest> {
+        self.0.iter().find_map(|s| s.take_manifest(order_path))
+    }
+}
+
+/// Ready when any inner strategy says so
+#[derive(Debug)]
+pub struct Any(pub Vec<Arc<dyn ReadinessStrategy>>);
+#[async_trait]
+impl ReadinessStrategy for Any {
+    async fn is_ready(&self, ctx: &mut ReadinessContext<'_>) -> Result<bool> {
+        for strategy in self.0.iter() {
+            if strategy.is_ready(ctx).await? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+    fn take_manifest(&self, order_path: &str) -> Option<Manifest> {
+        self.0.iter().find_map(|s| s.take_manifest(order_path))
+    }
+}
+
+/// Ready when a manifest flag file (matching `flag`) is present and every file it lists
+/// is present with the listed size; the manifest is kept to verify the downloaded files
+#[derive(Debug)]
+pub struct ManifestFlag {
+    flag: NamePattern,
+    ///Manifests of ready orders until taken; orders that never get queued are forgotten
+    manifests: OrderStates<Manifest>,
+}
+impl ManifestFlag {
+    pub fn new(flag: NamePattern) -> Self {
+        Self {
+            flag,
+            manifests: OrderStates::default(),
+        }
+    }
+}
+#[async_trait]
+impl ReadinessStrategy for ManifestFlag {
+    async fn is_ready(&self, ctx: &mut ReadinessContext<'_>) -> Result<bool> {
+        let flag = ctx
+            .entries
+            .iter()
+            .find(|e| !e.entry.is_dir() && self.flag.matches(&e.name()))
+            .map(|e| e.entry.get_full_path());
+        let flag = match flag {
+            Some(flag) => flag,
+            None => return Ok(false),
+        };
+        let manifest = Manifest::parse(&ctx.source.read(&flag).await?)?;
+        let prefix = format!("{}/", ctx.order_path);
+        let present: HashMap<String, u64> = ctx
+            .walk()
+            .await?
+            .into_iter()
+            .filter_map(|listed| match listed.entry {
+                FtpDirEntry::File(path, size) => path
+                    .strip_prefix(&prefix)
+                    .map(|rel| (rel.to_owned(), size as u64)),
+                FtpDirEntry::Folder(..) => None,
+            })
+            .collect();
+        if !manifest.is_satisfied_by(|name| present.get(name).copied()) {
+            return Ok(false);
+        }
+        self.manifests.insert(ctx.order_path, manifest);
+        Ok(true)
+    }
+    fn take_manifest(&self, order_path: &str) -> Option<Manifest> {
+        self.manifests.remove(order_path)
+    }
+}
+
+/// Readiness strategy as described in config file
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "strategy", rename_all = "snake_case")]
+pub enum ReadinessConfig {
+    FlagFile { name: String },
+    FlagPattern { pattern: NamePattern },
+    FlagExtension { ext: String },
+    Companion { suffix: String },
+    Stable { scans: u32 },
+    MinAge { seconds: u64 },
+    Manifest { flag: NamePattern },
+    All { of: Vec<ReadinessConfig> },
+    Any { of: Vec<ReadinessConfig> },
+}
+impl ReadinessConfig {
+    pub fn build(&self) -> Arc<dyn ReadinessStrategy> {
+        match self {
+            Self::FlagFile { name } => Arc::new(FlagFile(name.to_owned())),
+            Self::FlagPattern { pattern } => Arc::new(FlagPattern(pattern.to_owned())),
+            Self::FlagExtension { ext } => Arc::new(FlagExtension::new(ext)),
+            Self::Companion { suffix } => Arc::new(Companion(suffix.to_owned())),
+            Self::Stable { scans } => Arc::new(Stable::new(*scans)),
+            Self::MinAge { seconds } => Arc::new(MinAge::new(Duration::from_secs(*seconds))),
+            Self::Manifest { flag } => Arc::new(ManifestFlag::new(flag.to_owned())),
+            Self::All { of } => Arc::new(All(of.iter().map(|c| c.build()).collect())),
+            Self::Any { of } => Arc::new(Any(of.iter().map(|c| c.build()).collect())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CustomError;
+
+    /// Order trees kept in memory: listings by folder path
+    #[derive(Default)]
+    struct Memory(HashMap<String, Vec<ListedEntry>>);
+    impl Memory {
+        ///Add file (`size` set) or folder; `modified` as unix seconds, empty if unknown
+        fn add(&mut self, path: &str, size: Option<usize>, modified: &str) {
+            let (parent, _) = path.rsplit_once('/').unwrap();
+            let entry = match size {
+                Some(size) => FtpDirEntry::File(path.to_owned(), size),
+                None => FtpDirEntry::Folder(path.to_owned()),
+            };
+            let listing = self.0.entry(parent.to_owned()).or_default();
+            listing.retain(|e| e.entry.get_full_path() != path);
+            listing.push(ListedEntry {
+                entry,
+                modified: modified.to_owned(),
+            });
+            if size.is_none() {
+                self.0.entry(path.to_owned()).or_default();
+            }
+        }
+    }
+    #[async_trait]
+    impl EntrySource for Memory {
+        async fn list(&mut self, path: &str) -> Result<Vec<ListedEntry>> {
+            self.0
+                .get(path)
+                .cloned()
+                .ok_or_else(|| CustomError::Io(format!("{} not found", path)))
+        }
+        async fn read(&mut self, path: &str) -> Result<Vec<u8>> {
+            Err(CustomError::Io(format!("{} not readable", path)))
+        }
+    }
+
+    ///Check the order as `Order::is_ready_among` does, siblings from its parent folder
+    async fn check(strategy: &dyn ReadinessStrategy, source: &mut Memory, order: &str) -> bool {
+        let (parent, _) = order.rsplit_once('/').unwrap();
+        let siblings = source.list(parent).await.unwrap();
+        let mut ctx = ReadinessContext::new(order, source).await.unwrap();
+        ctx.siblings = siblings;
+        strategy.is_ready(&mut ctx).await.unwrap()
+    }
+
+    fn order_tree(modified: &str) -> Memory {
+        let mut source = Memory::default();
+        source.add("/in/o", None, modified);
+        source.add("/in/o/a.csv", Some(10), modified);
+        source.add("/in/o/sub", None, modified);
+        source.add("/in/o/sub/b.csv", Some(20), modified);
+        source
+    }
+
+    fn ago(secs: i64) -> String {
+        (chrono::Utc::now().timestamp() - secs).to_string()
+    }
+
+    #[tokio::test]
+    async fn stable_needs_unchanged_tree_for_scans_in_a_row() {
+        let strategy = Stable::new(2);
+        let mut source = order_tree("100");
+        assert!(!check(&strategy, &mut source, "/in/o").await);
+        assert!(check(&strategy, &mut source, "/in/o").await);
+        //nested file still growing
+        source.add("/in/o/sub/b.csv", Some(30), "100");
+        assert!(!check(&strategy, &mut source, "/in/o").await);
+        assert!(check(&strategy, &mut source, "/in/o").await);
+    }
+
+    #[tokio::test]
+    async fn all_keeps_counting_while_other_strategy_is_not_ready() {
+        let strategy = All(vec![
+            Arc::new(Companion(".done".into())),
+            Arc::new(Stable::new(2)),
+        ]);
+        let mut source = order_tree("100");
+        assert!(!check(&strategy, &mut source, "/in/o").await);
+        assert!(!check(&strategy, &mut source, "/in/o").await);
+        source.add("/in/o.done", Some(0), "100");
+        assert!(check(&strategy, &mut source, "/in/o").await);
+    }
+
+    #[tokio::test]
+    async fn any_is_ready_when_one_strategy_is() {
+        let strategy = Any(vec![
+            Arc::new(FlagFile("READY".into())),
+            Arc::new(FlagExtension::new("ok")),
+        ]);
+        let mut source = order_tree("100");
+        assert!(!check(&strategy, &mut source, "/in/o").await);
+        source.add("/in/o/batch.ok", Some(0), "100");
+        assert!(check(&strategy, &mut source, "/in/o").await);
+    }
+
+    #[tokio::test]
+    async fn flag_extension_ignores_case_and_nested_files() {
+        let strategy = FlagExtension::new(".TXT");
+        let mut source = order_tree("100");
+        source.add("/in/o/sub/flag.txt", Some(0), "100");
+        source.add("/in/o/flag.txt.part", Some(0), "100");
+        assert!(!check(&strategy, &mut source, "/in/o").await);
+        source.add("/in/o/FLAG.Txt", Some(0), "100");
+        assert!(check(&strategy, &mut source, "/in/o").await);
+    }
+
+    #[tokio::test]
+    async fn companion_is_looked_up_next_to_the_order() {
+        let strategy = Companion(".done".into());
+        let mut source = order_tree("100");
+        source.add("/in/o/o.done", Some(0), "100");
+        source.add("/in/other.done", Some(0), "100");
+        assert!(!check(&strategy, &mut source, "/in/o").await);
+        //single-file order
+        source.add("/in/data.csv", Some(5), "100");
+        source.add("/in/data.csv.done", Some(0), "100");
+        let siblings = source.list("/in").await.unwrap();
+        let mut ctx = ReadinessContext::for_file("/in/data.csv", siblings, &mut source);
+        assert!(strategy.is_ready(&mut ctx).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn min_age_goes_by_newest_listing_date() {
+        let strategy = MinAge::new(Duration::from_secs(600));
+        let mut source = order_tree(&ago(3600));
+        assert!(check(&strategy, &mut source, "/in/o").await);
+        source.add("/in/o/sub/c.csv", Some(1), &ago(60));
+        assert!(!check(&strategy, &mut source, "/in/o").await);
+    }
+
+    #[tokio::test]
+    async fn min_age_without_dates_counts_from_first_seen() {
+        let mut source = order_tree("");
+        assert!(!check(&MinAge::new(Duration::from_secs(600)), &mut source, "/in/o").await);
+        assert!(check(&MinAge::new(Duration::ZERO), &mut source, "/in/o").await);
+    }
+
+    #[test]
+    fn order_states_forget_orders_not_checked_for_a_while() {
+        let states: OrderStates<u32> = OrderStates::default();
+        states.insert("/in/old", 1);
+        let long_ago = match Instant::now().checked_sub(FORGET_AFTER + Duration::from_secs(1)) {
+            Some(at) => at,
+            //monotonic clock started too recently
+            None => return,
+        };
+        states.0.lock().unwrap().get_mut("/in/old").unwrap().1 = long_ago;
+        states.update("/in/new", || 0, |count| *count += 1);
+        assert_eq!(states.remove("/in/old"), None);
+        assert_eq!(states.remove("/in/new"), Some(1));
+    }
+
+    #[test]
+    fn order_state_is_kept_while_checked() {
+        let states: OrderStates<u32> = OrderStates::default();
+        for _ in 0..3 {
+            states.update("/in/o", || 0, |count| *count += 1);
+        }
+        assert_eq!(states.remove("/in/o"), Some(3));
+    }
+}
@@ -0,0 +1,367 @@
+use crate::{NamePattern, Order};
+use serde::{Deserialize, Serialize};
+use std::{
+    cmp::{Ordering, Reverse},
+    collections::{BinaryHeap, HashMap},
+};
+
+///Pass increment of a folder with weight 1 (stride scheduling)
+const STRIDE: u64 = 1 << 20;
+
+/// Order in which pending orders are downloaded
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SchedulePolicy {
+    ///In order of discovery
+    #[default]
+    Fifo,
+    ///Order with the oldest remote file first
+    OldestFirst,
+    ///Order with the least bytes first
+    SmallestFirst,
+}
+
+/// Orders whose remote path matches the pattern get the priority (higher goes first, default 0)
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PriorityRule {
+    pub pattern: NamePattern,
+    pub priority: i32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct SchedulerConfig {
+    pub policy: SchedulePolicy,
+    ///First matching rule wins
+    pub priorities: Vec<PriorityRule>,
+    ///Share downloads between watched folders by their weight instead of one common queue
+    pub fair_share: bool,
+}
+
+struct Queued {
+    priority: i32,
+    key: i64,
+    seq: u64,
+    order: Order,
+}
+impl Queued {
+    ///Greater goes first: higher priority, then smaller policy key, then earlier discovery
+    fn rank(&self) -> (i32, Reverse<i64>, Reverse<u64>) {
+        (self.priority, Reverse(self.key), Reverse(self.seq))
+    }
+}
+impl PartialEq for Queued {
+    fn eq(&self, other: &Self) -> bool {
+        self.rank() == other.rank()
+    }
+}
+impl Eq for Queued {}
+impl PartialOrd for Queued {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl Ord for Queued {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.rank().cmp(&other.rank())
+    }
+}
+
+#[derive(Default)]
+struct FolderQueue {
+    heap: BinaryHeap<Queued>,
+    ///Orders of the heap that were not removed
+    live: usize,
+    weight: u32,
+    pass: u64,
+}
+
+/// Queue of pending orders;
+/// Push and pop are O(log n), plus O(number of watched folders) for fair share;
+/// Removed orders stay in their heap and are skipped when they surface
+pub struct Scheduler {
+    config: SchedulerConfig,
+    queues: HashMap<String, FolderQueue>,
+    ///Order path => sequence number of its live queue entry
+    live: HashMap<String, u64>,
+    seq: u64,
+    ///Pass of the folder picked last, new folders start from it so they don't get a burst
+    pass: u64,
+}
+
+impl std::fmt::Debug for Scheduler {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Scheduler")
+            .field("config", &self.config)
+            .field("pending", &self.live.len())
+            .finish()
+    }
+}
+
+impl Scheduler {
+    pub fn new(config: SchedulerConfig) -> Self {
+        Self {
+            config,
+            queues: HashMap::new(),
+            live: HashMap::new(),
+            seq: 0,
+            pass: 0,
+        }
+    }
+    pub fn len(&self) -> usize {
+        self.live.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.live.is_empty()
+    }
+    pub fn contains(&self, order_path: &str) -> bool {
+        self.live.contains_key(order_path)
+    }
+    ///Queue order with priority of the first matching rule; does nothing if it is queued already
+    pub fn push(&mut self, order: Order) {
+        let path = order.get_root_path();
+        let priority = self
+            .config
+            .priorities
+            .iter()
+            .find(|rule| rule.pattern.matches(&path))
+            .map_or(0, |rule| rule.priority);
+        self.push_with_priority(order, priority);
+    }
+    ///Queue order with explicit priority; does nothing if it is queued already
+    pub fn push_with_priority(&mut self, order: Order, priority: i32) {
+        let path = order.get_root_path();
+        if self.live.contains_key(&path) {
+            return;
+        }
+        let key = match self.config.policy {
+            SchedulePolicy::Fifo => 0,
+            //orders with unknown dates go last
+            SchedulePolicy::OldestFirst => order.get_oldest_modified().unwrap_or(i64::MAX),
+            SchedulePolicy::SmallestFirst => order.get_total_size() as i64,
+        };
+        self.seq += 1;
+        let folder = self.folder_of(&order);
+        let pass = self.pass;
+        let queue = self.queues.entry(folder).or_default();
+        if queue.live == 0 {
+            queue.pass = queue.pass.max(pass);
+        }
+        queue.weight = order.get_watch_dir().weight.max(1);
+        queue.live += 1;
+        queue.heap.push(Queued {
+            priority,
+            key,
+            seq: self.seq,
+            order,
+        });
+        self.live.insert(path, self.seq);
+    }
+    ///Take next order to download
+    pub fn pop(&mut self) -> Option<Order> {
+        self.pop_where(|_| true)
+    }
+    ///Take next order `allow` accepts; skipped orders keep their place;
+    /// Costs O(log n) more for every skipped order
+    pub fn pop_where(&mut self, allow: impl Fn(&Order) -> bool) -> Option<Order> {
+        //folder with the least pass is the one most behind its share
+        let mut folders: Vec<(u64, String)> = self
+            .queues
+            .iter()
+            .filter(|(_, q)| q.live > 0)
+            .map(|(path, q)| (q.pass, path.to_owned()))
+            .collect();
+        folders.sort();
+        for (_, folder) in folders {
+            let queue = self.queues.get_mut(&folder)?;
+            let mut skipped = Vec::new();
+            let mut found = None;
+            while let Some(queued) = queue.heap.pop() {
+                let path = queued.order.get_root_path();
+                if self.live.get(&path) != Some(&queued.seq) {
+                    //removed or queued again with another priority
+                    continue;
+                }
+                if allow(&queued.order) {
+                    found = Some((path, queued));
+                    break;
+                }
+                skipped.push(queued);
+            }
+            queue.heap.extend(skipped);
+            if let Some((path, queued)) = found {
+                self.live.remove(&path);
+                queue.live -= 1;
+                self.pass = queue.pass;
+                queue.pass += STRIDE / queue.weight as u64;
+                return Some(queued.order);
+            }
+        }
+        None
+    }
+    ///Remove queued order; returns it if it was queued;
+    /// Linear in queue size, meant for occasional operator actions
+    pub fn remove(&mut self, order_path: &str) -> Option<Order> {
+        let seq = self.live.remove(order_path)?;
+        let queue = self.queues.values_mut().find(|q| {
+            q.heap
+                .iter()
+                .any(|e| e.seq == seq && e.order.get_root_path() == order_path)
+        })?;
+        queue.live -= 1;
+        queue
+            .heap
+            .iter()
+            .find(|e| e.seq == seq)
+            .map(|e| e.order.to_owned())
+    }
+    ///Queued orders in no particular order
+    pub fn iter(&self) -> impl Iterator<Item = &Order> {
+        self.queues.values().flat_map(move |q| {
+            q.heap
+                .iter()
+                .filter(move |e| self.live.get(&e.order.get_root_path()) == Some(&e.seq))
+                .map(|e| &e.order)
+        })
+    }
+    fn folder_of(&self, order: &Order) -> String {
+        if self.config.fair_share {
+            order.get_watch_dir().path.to_owned()
+        } else {
+            String::new()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{FtpDirEntry, WatchDir};
+
+    fn order(watch_dir: &str, name: &str) -> Order {
+        weighted(watch_dir, name, 1)
+    }
+    fn weighted(watch_dir: &str, name: &str, weight: u32) -> Order {
+        let mut dir = WatchDir::new(watch_dir);
+        dir.weight = weight;
+        Order::new(
+            &FtpDirEntry::Folder(format!("{}/{}", watch_dir, name)),
+            "/local",
+            &dir,
+        )
+    }
+    fn sized(name: &str, size: usize) -> Order {
+        let mut job = order("in", name);
+        job.set_entries(
+            vec![FtpDirEntry::File(format!("in/{}/f", name), size)],
+            Vec::new(),
+        );
+        job
+    }
+    fn drain(scheduler: &mut Scheduler) -> Vec<String> {
+        std::iter::from_fn(|| scheduler.pop())
+            .map(|o| o.get_root_path())
+            .collect()
+    }
+
+    #[test]
+    fn fifo_keeps_discovery_order_and_ignores_duplicates() {
+        let mut scheduler = Scheduler::new(SchedulerConfig::default());
+        scheduler.push(order("in", "a"));
+        scheduler.push(order("in", "b"));
+        scheduler.push(order("in", "a"));
+        scheduler.push(order("in", "c"));
+        assert_eq!(scheduler.len(), 3);
+        assert_eq!(drain(&mut scheduler), ["in/a", "in/b", "in/c"]);
+        assert!(scheduler.is_empty());
+    }
+
+    #[test]
+    fn priority_rules_go_before_policy() {
+        let mut scheduler = Scheduler::new(SchedulerConfig {
+            policy: SchedulePolicy::SmallestFirst,
+            priorities: vec![PriorityRule {
+                pattern: NamePattern::new("*urgent*").unwrap(),
+                priority: 10,
+            }],
+            fair_share: false,
+        });
+        scheduler.push(sized("big", 100));
+        scheduler.push(sized("small", 1));
+        scheduler.push(sized("urgent_big", 1000));
+        assert_eq!(
+            drain(&mut scheduler),
+            ["in/urgent_big", "in/small", "in/big"]
+        );
+    }
+
+    #[test]
+    fn removed_order_is_skipped() {
+        let mut scheduler = Scheduler::new(SchedulerConfig::default());
+        scheduler.push(order("in", "a"));
+        scheduler.push(order("in", "b"));
+        let removed = scheduler.remove("in/a").map(|o| o.get_root_path());
+        assert_eq!(removed.as_deref(), Some("in/a"));
+        assert!(scheduler.remove("in/a").is_none());
+        assert!(!scheduler.contains("in/a"));
+        assert_eq!(scheduler.iter().count(), 1);
+        assert_eq!(drain(&mut scheduler), ["in/b"]);
+    }
+
+    #[test]
+    fn order_pushed_again_with_new_priority_is_popped_once() {
+        let mut scheduler = Scheduler::new(SchedulerConfig::default());
+        scheduler.push(order("in", "a"));
+        scheduler.push(order("in", "b"));
+        let b = scheduler.remove("in/b").unwrap();
+        scheduler.push_with_priority(b, 5);
+        assert_eq!(scheduler.len(), 2);
+        assert_eq!(drain(&mut scheduler), ["in/b", "in/a"]);
+    }
+
+    #[test]
+    fn pop_where_keeps_place_of_skipped_orders() {
+        let mut scheduler = Scheduler::new(SchedulerConfig::default());
+        for name in ["a", "b", "c"] {
+            scheduler.push(order("in", name));
+        }
+        let picked = scheduler.pop_where(|o| o.get_root_path() != "in/a");
+        assert_eq!(picked.map(|o| o.get_root_path()).as_deref(), Some("in/b"));
+        assert!(scheduler.pop_where(|_| false).is_none());
+        assert_eq!(drain(&mut scheduler), ["in/a", "in/c"]);
+    }
+
+    #[test]
+    fn fair_share_splits_downloads_by_weight() {
+        let mut scheduler = Scheduler::new(SchedulerConfig {
+            fair_share: true,
+            ..Default::default()
+        });
+        for i in 0..8 {
+            scheduler.push(weighted("heavy", &i.to_string(), 3));
+            scheduler.push(weighted("light", &i.to_string(), 1));
+        }
+        let first: Vec<String> = drain(&mut scheduler).into_iter().take(8).collect();
+        let heavy = first.iter().filter(|p| p.starts_with("heavy/")).count();
+        assert_eq!(heavy, 6);
+    }
+
+    #[test]
+    fn folder_joining_late_gets_no_burst() {
+        let mut scheduler = Scheduler::new(SchedulerConfig {
+            fair_share: true,
+            ..Default::default()
+        });
+        for i in 0..4 {
+            scheduler.push(order("a", &i.to_string()));
+        }
+        scheduler.pop();
+        scheduler.pop();
+        for i in 0..4 {
+            scheduler.push(order("b", &i.to_string()));
+        }
+        let next: Vec<String> = drain(&mut scheduler).into_iter().take(4).collect();
+        let from_b = next.iter().filter(|p| p.starts_with("b/")).count();
+        assert_eq!(from_b, 2);
+    }
+}
@@ -0,0 +1,137 @@
+use crate::{FtpDirEntry, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+/// Remote file of a fetched order
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ListedFile {
+    pub path: String,
+    pub size: usize,
+    ///Listing date, unix seconds; None if the server's format was not understood
+    #[serde(default)]
+    pub modified: Option<i64>,
+}
+
+/// Every file of a fetched order, sorted by path
+pub type FileList = Vec<ListedFile>;
+
+/// Orders fetched in copy and mirror modes, persisted as json so restarts don't fetch them again
+#[derive(Debug)]
+pub struct SyncState {
+    path: PathBuf,
+    orders: Mutex<HashMap<String, FileList>>,
+}
+impl SyncState {
+    ///Load state file; missing or unreadable file means nothing was fetched yet
+    pub fn load(path: PathBuf) -> Self {
+        let orders = std::fs::read(&path)
+            .ok()
+            .and_then(|content| serde_json::from_slice(&content).ok())
+            .unwrap_or_default();
+        Self {
+            path,
+            orders: Mutex::new(orders),
+        }
+    }
+    pub fn contains(&self, order_path: &str) -> bool {
+        self.lock().contains_key(order_path)
+    }
+    pub fn get(&self, order_path: &str) -> Option<FileList> {
+        self.lock().get(order_path).cloned()
+    }
+    ///Fetched orders inside the watched folder
+    pub fn orders_under(&self, root: &str) -> Vec<String> {
+        let prefix = format!("{}/", root);
+        self.lock()
+            .keys()
+            .filter(|path| path.starts_with(&prefix))
+            .cloned()
+            .collect()
+    }
+    pub async fn record(&self, order_path: &str, files: FileList) -> Result<()> {
+        self.lock().insert(order_path.to_owned(), files);
+        self.save().await
+    }
+    pub async fn remove(&self, order_path: &str) -> Result<()> {
+        self.lock().remove(order_path);
+        self.save().await
+    }
+    //write to temp file first so a crash never leaves truncated state
+    async fn save(&self) -> Result<()> {
+        let content = serde_json::to_vec(&*self.lock())?;
+        if let Some(dir) = self.path.parent() {
+            tokio::fs::create_dir_all(dir).await?;
+        }
+        let tmp = self.path.with_extension("tmp");
+        tokio::fs::write(&tmp, content).await?;
+        tokio::fs::rename(&tmp, &self.path).await?;
+        Ok(())
+    }
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, FileList>> {
+        self.orders.lock().expect("sync state lock poisoned")
+    }
+}
+
+pub fn file_list(files: &[FtpDirEntry]) -> FileList {
+    dated_file_list(files, |_| None)
+}
+
+///Same as `file_list`, with listing dates `modified` knows of
+pub fn dated_file_list(files: &[FtpDirEntry], modified: impl Fn(&str) -> Option<i64>) -> FileList {
+    let mut list: FileList = files
+        .iter()
+        .filter_map(|f| match f {
+            FtpDirEntry::File(path, size) => Some(ListedFile {
+                path: path.to_owned(),
+                size: *size,
+                modified: modified(path),
+            }),
+            FtpDirEntry::Folder(..) => None,
+        })
+        .collect();
+    list.sort();
+    list
+}
+
+///Same files with the same sizes and listing dates; a date missing on either side matches any date,
+/// e.g. state recorded before dates were kept
+pub fn same_files(previous: &[ListedFile], current: &[ListedFile]) -> bool {
+    previous.len() == current.len()
+        && previous.iter().zip(current).all(|(p, c)| {
+            p.path == c.path
+                && p.size == c.size
+                && (p.modified.is_none() || c.modified.is_none() || p.modified == c.modified)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(path: &str, size: usize, modified: Option<i64>) -> ListedFile {
+        ListedFile {
+            path: path.to_owned(),
+            size,
+            modified,
+        }
+    }
+
+    #[test]
+    fn loads_file_lists_saved_without_dates() {
+        let orders: HashMap<String, FileList> =
+            serde_json::from_str(r#"{"/in/o": [["/in/o/a", 3]]}"#).unwrap();
+        assert_eq!(orders["/in/o"], vec![file("/in/o/a", 3, None)]);
+    }
+
+    #[test]
+    fn changed_listing_date_is_changed_content() {
+        let previous = vec![file("/in/o/a", 3, Some(100))];
+        assert!(same_files(&previous, &[file("/in/o/a", 3, Some(100))]));
+        assert!(same_files(&previous, &[file("/in/o/a", 3, None)]));
+        assert!(!same_files(&previous, &[file("/in/o/a", 3, Some(160))]));
+        assert!(!same_files(&previous, &[file("/in/o/a", 4, Some(100))]));
+        assert!(!same_files(&previous, &[]));
+    }
+}
@@ -0,0 +1,147 @@
+use crate::{CircuitState, ConnectionStatus, Order, OrderState, Paused, Progress};
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// How many finished orders of each kind are kept for `Status`
+const RECENT_ORDERS: usize = 50;
+
+/// Snapshot of what the engine is doing, returned by `Watcher::status`
+#[derive(Debug, Clone, Serialize)]
+pub struct Status {
+    ///unix time, seconds
+    pub at: u64,
+    pub circuit: CircuitState,
+    ///Set while the server rejects credentials and reconnects are stopped
+    pub auth_failure: Option<String>,
+    ///Queued orders, in no particular order
+    pub pending: Vec<OrderSummary>,
+    pub running: Vec<OrderSummary>,
+    ///Failed orders waiting for their next attempt
+    pub retrying: Vec<OrderSummary>,
+    pub dead_letters: Vec<OrderSummary>,
+    ///Newest first
+    pub recent_completed: Vec<OrderSummary>,
+    ///Every failed attempt, newest first
+    pub recent_failed: Vec<OrderSummary>,
+    pub connections: Vec<ConnectionStatus>,
+    pub scans: Vec<ScanStatus>,
+    pub counters: Counters,
+    pub paused: Paused,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct OrderSummary {
+    pub order: String,
+    pub watch_dir: String,
+    pub state: OrderState,
+    pub files: usize,
+    pub bytes: u64,
+    pub attempts: u32,
+    pub last_error: Option<String>,
+    ///Running orders while downloading
+    pub progress: Option<Progress>,
+    ///When the order finished, for recent orders; unix time, seconds
+    pub finished_at: Option<u64>,
+}
+impl OrderSummary {
+    pub fn of(job: &Order) -> Self {
+        Self {
+            order: job.get_root_path(),
+            watch_dir: job.get_watch_dir().path.to_owned(),
+            state: job.get_state(),
+            files: job.get_files_list().map_or(0, |f| f.len()),
+            bytes: job.get_total_size() as u64,
+            attempts: job.get_attempts(),
+            last_error: job.get_last_error().map(|e| e.to_owned()),
+            progress: None,
+            finished_at: None,
+        }
+    }
+}
+
+/// Result of the last scan of a watched folder
+#[derive(Debug, Clone, Serialize)]
+pub struct ScanStatus {
+    pub watch_dir: String,
+    ///unix time, seconds
+    pub at: u64,
+    pub duration_ms: u64,
+    pub orders_found: usize,
+    pub error: Option<String>,
+}
+
+/// Totals since the engine started
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Counters {
+    pub orders_discovered: u64,
+    pub orders_completed: u64,
+    ///Failed attempts, dead-lettered orders included
+    pub orders_failed: u64,
+    pub orders_dead_lettered: u64,
+    pub files_downloaded: u64,
+    pub bytes_downloaded: u64,
+    pub remote_entries_deleted: u64,
+    pub scans: u64,
+    pub scan_errors: u64,
+}
+
+/// Counters updated by the engine tasks
+#[derive(Debug, Default)]
+pub(crate) struct LiveCounters {
+    pub orders_discovered: AtomicU64,
+    pub orders_completed: AtomicU64,
+    pub orders_failed: AtomicU64,
+    pub orders_dead_lettered: AtomicU64,
+    pub files_downloaded: AtomicU64,
+    pub bytes_downloaded: AtomicU64,
+    pub remote_entries_deleted: AtomicU64,
+    pub scans: AtomicU64,
+    pub scan_errors: AtomicU64,
+}
+impl LiveCounters {
+    pub fn add(counter: &AtomicU64, n: u64) {
+        counter.fetch_add(n, Ordering::Relaxed);
+    }
+    pub fn snapshot(&self) -> Counters {
+        let get = |c: &AtomicU64| c.load(Ordering::Relaxed);
+        Counters {
+            orders_discovered: get(&self.orders_discovered),
+            orders_completed: get(&self.orders_completed),
+            orders_failed: get(&self.orders_failed),
+            orders_dead_lettered: get(&self.orders_dead_lettered),
+            files_downloaded: get(&self.files_downloaded),
+            bytes_downloaded: get(&self.bytes_downloaded),
+            remote_entries_deleted: get(&self.remote_entries_deleted),
+            scans: get(&self.scans),
+            scan_errors: get(&self.scan_errors),
+        }
+    }
+}
+
+/// Last finished orders, oldest are dropped
+#[derive(Debug, Default)]
+pub(crate) struct Recent(VecDeque<OrderSummary>);
+impl Recent {
+    ///Record order that finished in the given state
+    pub fn push(&mut self, job: &Order, state: OrderState) {
+        let mut summary = OrderSummary::of(job);
+        summary.state = state;
+        summary.finished_at = Some(now());
+        if self.0.len() == RECENT_ORDERS {
+            self.0.pop_back();
+        }
+        self.0.push_front(summary);
+    }
+    pub fn to_vec(&self) -> Vec<OrderSummary> {
+        self.0.iter().cloned().collect()
+    }
+}
+
+pub(crate) fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
@@ -0,0 +1,442 @@
+use crate::{
+    metrics, state, status, utils, AfterUpload, Connection, CustomError, EntrySource, Event,
+    FtpDirEntry, LocalFs, NamePattern, Order, OrderState, Pool, ReadinessStrategy, Result,
+    SyncState, UploadDir,
+};
+use std::sync::Arc;
+use tokio::{
+    join, spawn,
+    sync::{Mutex, MutexGuard},
+};
+use tracing::{error, info, info_span, warn, Instrument};
+
+/// Uploader is the mirror image of Watcher: it watches local folders for finished orders
+/// (same readiness strategies) and sends them to the server over transfer connections of the shared pool;
+/// Files are stored under a temporary name and renamed, flag files are sent last
+#[derive(Debug)]
+pub struct Uploader {
+    connections_pool: Arc<Pool>,
+    upload_dirs: Vec<(UploadDir, Arc<dyn ReadinessStrategy>, NamePattern)>,
+    pending_uploads: Mutex<Vec<Order>>,
+    running_uploads: Mutex<Vec<Order>>,
+    ///Uploads waiting for their retry, or out of attempts until requeued
+    failed_uploads: Mutex<Vec<Order>>,
+    ///Kept orders, and orders uploaded but left in place because deleting or archiving them failed
+    sync_state: SyncState,
+    ///Uploads out of attempts, not sent again after restart while their content stays the same
+    dead_letter_state: SyncState,
+    profile: String,
+}
+
+impl Uploader {
+    ///Create uploader sharing the pool with watcher (see `Watcher::get_pool_ref`)
+    pub async fn with_pool(pool: Arc<Pool>) -> Result<Uploader> {
+        let config = pool.get_config();
+        let upload_dirs = config
+            .get_upload_dirs()
+            .into_iter()
+            .map(|dir| {
+                let readiness = config.get_upload_readiness(&dir).build();
+                let flag = config.get_upload_flag(&dir);
+                (dir, readiness, flag)
+            })
+            .collect();
+        Ok(Uploader {
+            connections_pool: pool,
+            upload_dirs,
+            pending_uploads: Mutex::new(Vec::new()),
+            running_uploads: Mutex::new(Vec::new()),
+            failed_uploads: Mutex::new(Vec::new()),
+            sync_state: SyncState::load(config.get_upload_state_file_path()),
+            dead_letter_state: SyncState::load(config.get_upload_dead_letters_file_path()),
+            profile: config.get_profile(),
+        })
+    }
+    /// This function will never return;
+    /// Scans local upload folders for finished orders and uploads them
+    pub async fn watch(&'static self) {
+        let uploader_handle = self.spawn_uploader_worker();
+        let watcher_handle = self.spawn_local_watcher_worker();
+        let (_, _) = join!(watcher_handle, uploader_handle);
+    }
+    ///Replace readiness strategy of all upload folders with a custom one
+    pub fn set_readiness_strategy(&mut self, strategy: Arc<dyn ReadinessStrategy>) {
+        for (_, readiness, _) in self.upload_dirs.iter_mut() {
+            *readiness = strategy.clone();
+        }
+    }
+    ///Snapshot of uploads that exhausted their retries
+    pub async fn dead_letters(&self) -> Vec<Order> {
+        self.failed_uploads
+            .lock()
+            .await
+            .iter()
+            .filter(|job| job.get_state() == OrderState::DeadLettered)
+            .cloned()
+            .collect()
+    }
+    ///Snapshot of failed uploads waiting for their next attempt
+    pub async fn failed_uploads(&self) -> Vec<Order> {
+        self.failed_uploads
+            .lock()
+            .await
+            .iter()
+            .filter(|job| job.get_state() != OrderState::DeadLettered)
+            .cloned()
+            .collect()
+    }
+    ///Move dead-lettered upload back to pending queue with a fresh set of attempts;
+    /// Returns false if there is no such dead-lettered upload
+    pub async fn requeue_dead_letter(&self, order_path: &str) -> bool {
+        let mut job = {
+            let mut failed = self.failed_uploads.lock().await;
+            match failed.iter().position(|j| {
+                j.get_root_path() == order_path && j.get_state() == OrderState::DeadLettered
+            }) {
+                Some(i) => failed.remove(i),
+                None => return false,
+            }
+        };
+        if let Err(e) = self.dead_letter_state.remove(order_path).await {
+            error!(order = %order_path, error = ?e, "failed to save upload dead letters");
+        }
+        job.requeue();
+        self.emit_state(&job);
+        self.push_pending(job).await;
+        true
+    }
+    fn emit_state(&self, job: &Order) {
+        self.connections_pool.emit(Event::OrderStateChanged {
+            order: job.get_root_path(),
+            state: job.get_state(),
+        });
+    }
+    ///Get free transfer connection from pool as mutex guard
+    pub async fn get_connection(&'static self) -> Result<MutexGuard<'static, Connection>> {
+        self.connections_pool.get_free_connection().await
+    }
+
+    ///Spawns task that starts infinite loop checking local upload folders for orders;
+    /// Every ready subfolder that was not uploaded yet is put in pending uploads queue
+    fn spawn_local_watcher_worker(&'static self) -> tokio::task::JoinHandle<()> {
+        spawn(async move {
+            let mut interval = tokio::time::interval(std::time::Duration::from_secs(2));
+            interval.tick().await;
+            loop {
+                for (upload_dir, readiness, _) in self.upload_dirs.iter() {
+                    let folders = match list_orders(upload_dir).await {
+                        Ok(folders) => folders,
+                        Err(e) => {
+                            warn!(path = %upload_dir.local_path, error = ?e, "failed to list upload folder");
+                            continue;
+                        }
+                    };
+                    for folder in folders {
+                        let job = Order::for_upload(&folder, &upload_dir.remote_path, upload_dir);
+                        self.check_order(job, upload_dir, readiness.as_ref()).await;
+                    }
+                }
+                interval.tick().await;
+            }
+        })
+    }
+    ///Check whether found local order should be uploaded; if so => put it in pending queue
+    async fn check_order(
+        &self,
+        mut job: Order,
+        upload_dir: &UploadDir,
+        readiness: &dyn ReadinessStrategy,
+    ) {
+        let is_running = self.running_uploads.lock().await.contains(&job);
+        let is_pending = self.pending_uploads.lock().await.contains(&job);
+        let is_failed = self.failed_uploads.lock().await.contains(&job);
+        if is_running || is_pending || is_failed {
+            return;
+        }
+        if upload_dir.after_upload == AfterUpload::Keep
+            && self.sync_state.contains(&job.get_root_path())
+        {
+            return;
+        }
+        let mut source = LocalFs;
+        match job.is_ready(&mut source, readiness).await {
+            Ok(true) => {}
+            Ok(false) => return,
+            Err(e) => {
+                warn!(order = %job.get_root_path(), error = ?e, "failed to check order readiness");
+                self.connections_pool
+                    .emit(Event::error(&e, Some(&job.get_root_path())));
+                return;
+            }
+        }
+        if job.read_all_entries_from(&mut source).await.is_err() {
+            return;
+        }
+        //uploaded already, but it could not be deleted or archived
+        let files = state::file_list(&job.get_files_list().unwrap_or_default());
+        if self.sync_state.get(&job.get_root_path()).as_ref() == Some(&files) {
+            return;
+        }
+        //dead-lettered before restart: listed again, but only sent once requeued
+        if self.dead_letter_state.get(&job.get_root_path()) == Some(files) {
+            job.set_state(OrderState::DeadLettered);
+            self.failed_uploads.lock().await.push(job);
+            return;
+        }
+        job.set_state(OrderState::Queued);
+        self.emit_state(&job);
+        self.push_pending(job).await;
+    }
+    ///Spawns a task that runs infinite loop:
+    /// On every loop iteration check queue for pending uploads;
+    /// If queue is not empty - get next order and run upload process;
+    fn spawn_uploader_worker(&'static self) -> tokio::task::JoinHandle<()> {
+        spawn(async move {
+            let mut interval = tokio::time::interval(std::time::Duration::from_secs(2));
+            interval.tick().await;
+            loop {
+                self.requeue_due_failures().await;
+                if let Some(job) = self.pending_uploads.lock().await.first().cloned() {
+                    if let Ok(conn) = self.get_connection().await {
+                        self.pending_uploads.lock().await.retain(|j| *j != job);
+                        self.running_uploads.lock().await.push(job.to_owned());
+                        self.spawn_upload_task(job, conn);
+                    }
+                }
+                interval.tick().await;
+            }
+        })
+    }
+    ///Spawn task that uploads local order to the server, flag files last,
+    /// then deletes, archives or remembers the local order
+    fn spawn_upload_task(
+        &'static self,
+        job: Order,
+        mut conn: MutexGuard<'static, Connection>,
+    ) -> tokio::task::JoinHandle<()> {
+        let span = info_span!("upload", order = %job.get_root_path());
+        let task = async move {
+            let job_path = job.get_root_path();
+            let (upload_dir, _, flag) = match self
+                .upload_dirs
+                .iter()
+                .find(|(dir, ..)| dir.local_path == job.get_watch_dir().path)
+            {
+                Some(dir) => dir,
+                None => {
+                    warn!(order = %job_path, "upload folder is not configured anymore");
+                    self.running_uploads.lock().await.retain(|j| *j != job);
+                    return;
+                }
+            };
+            let files = job.get_files_list().unwrap_or_default();
+            let pairs = Uploader::get_upload_pairs(&job, upload_dir, flag, &files);
+            let uploaded = conn
+                .batch_upload_with(&pairs, |remote, size| {
+                    metrics::file_uploaded(&self.profile, &upload_dir.local_path, size);
+                    self.connections_pool.emit(Event::FileUploaded {
+                        order: job_path.to_owned(),
+                        path: remote.to_owned(),
+                        size: size as usize,
+                    });
+                })
+                .await;
+            match uploaded {
+                Ok(()) => {
+                    info!(order = %job_path, "uploaded order");
+                    drop(conn);
+                    //content changed since it was dead-lettered, and it went through this time
+                    if self.dead_letter_state.contains(&job_path) {
+                        if let Err(e) = self.dead_letter_state.remove(&job_path).await {
+                            error!(order = %job_path, error = ?e, "failed to save upload dead letters");
+                        }
+                    }
+                    metrics::upload_completed(&self.profile, &upload_dir.local_path);
+                    self.connections_pool.emit(Event::OrderStateChanged {
+                        order: job_path.to_owned(),
+                        state: OrderState::Done,
+                    });
+                    if let Err(e) =
+                        Uploader::finish_local(&job, upload_dir, &files, &self.sync_state).await
+                    {
+                        warn!(order = %job_path, error = ?e, "failed to finish local order");
+                        //never send it again while it stays in the upload folder
+                        if let Err(e) = self
+                            .sync_state
+                            .record(&job_path, state::file_list(&files))
+                            .await
+                        {
+                            error!(order = %job_path, error = ?e, "failed to save upload state");
+                        }
+                    }
+                }
+                Err(e) => {
+                    drop(conn);
+                    self.fail_upload(job.to_owned(), e).await;
+                }
+            }
+            self.running_uploads.lock().await.retain(|j| *j != job);
+        };
+        spawn(task.instrument(span))
+    }
+    ///(local, remote) path of every file of the order, flag files last
+    fn get_upload_pairs(
+        job: &Order,
+        upload_dir: &UploadDir,
+        flag: &NamePattern,
+        files: &[FtpDirEntry],
+    ) -> Vec<(String, String)> {
+        let prefix = format!("{}/", upload_dir.local_path);
+        let mut pairs: Vec<(String, String)> = files
+            .iter()
+            .filter(|f| !f.is_dir())
+            .map(|f| {
+                let local = f.get_full_path();
+                let relative = local.strip_prefix(&prefix).unwrap_or(&local).to_owned();
+                (local, format!("{}/{}", job.get_target_path(), relative))
+            })
+            .collect();
+        //stable sort keeps the original order within both groups
+        pairs.sort_by_key(|(local, _)| flag.matches(utils::get_file_name(local)));
+        pairs
+    }
+    ///Apply `after_upload` action to the uploaded local order
+    async fn finish_local(
+        job: &Order,
+        upload_dir: &UploadDir,
+        files: &[FtpDirEntry],
+        sync_state: &SyncState,
+    ) -> Result<()> {
+        let job_path = job.get_root_path();
+        match upload_dir.after_upload {
+            AfterUpload::Keep => sync_state.record(&job_path, state::file_list(files)).await,
+            AfterUpload::Delete => Ok(tokio::fs::remove_dir_all(&job_path).await?),
+            AfterUpload::Archive => {
+                Uploader::archive_local(&job_path, &upload_dir.get_archive_path()).await
+            }
+        }
+    }
+    ///Move order into the archive folder;
+    /// Existing archived order of the same name is not overwritten, timestamp is appended instead
+    async fn archive_local(path: &str, archive_path: &str) -> Result<()> {
+        tokio::fs::create_dir_all(archive_path).await?;
+        let mut target = format!("{}/{}", archive_path, utils::get_file_name(path));
+        if tokio::fs::try_exists(&target).await? {
+            target = format!("{}_{}", target, status::now());
+        }
+        tokio::fs::rename(path, &target).await?;
+        Ok(())
+    }
+    ///Schedule retry of failed upload by the `retry` policy; out of attempts it waits to be requeued
+    async fn fail_upload(&self, mut job: Order, e: CustomError) {
+        let retry = self.connections_pool.get_config().retry;
+        let order = job.get_root_path();
+        let error = format!("{:?}", e);
+        self.connections_pool.emit(Event::error(&e, Some(&order)));
+        metrics::upload_failed(&self.profile, &job.get_watch_dir().path);
+        let state = job.fail(error.to_owned(), &retry);
+        self.emit_state(&job);
+        match state {
+            OrderState::DeadLettered => {
+                error!(
+                    order = %order,
+                    attempts = job.get_attempts(),
+                    error = %error,
+                    "upload failed, retries exhausted"
+                );
+                let files = job.get_files_list().unwrap_or_default();
+                if let Err(e) = self
+                    .dead_letter_state
+                    .record(&order, state::file_list(&files))
+                    .await
+                {
+                    error!(order = %order, error = ?e, "failed to save upload dead letters");
+                }
+            }
+            _ => warn!(
+                order = %order,
+                attempt = job.get_attempts(),
+                max_attempts = retry.max_attempts,
+                retry_in = ?retry.delay(job.get_attempts()),
+                error = %error,
+                "upload failed"
+            ),
+        }
+        self.failed_uploads.lock().await.push(job);
+    }
+    ///Move failed uploads whose backoff elapsed back to pending queue
+    async fn requeue_due_failures(&self) {
+        let due: Vec<Order> = {
+            let mut failed = self.failed_uploads.lock().await;
+            let (due, waiting) = failed.drain(..).partition(|job| job.is_retry_due());
+            *failed = waiting;
+            due
+        };
+        for mut job in due {
+            job.set_state(OrderState::Queued);
+            self.emit_state(&job);
+            self.push_pending(job).await;
+        }
+    }
+    ///Push provided job to pending queue
+    async fn push_pending(&self, job: Order) {
+        let mut pending = self.pending_uploads.lock().await;
+        if pending.contains(&job) {
+            return;
+        }
+        pending.push(job);
+    }
+}
+
+///Subfolders of the upload folder, except its archive folder
+async fn list_orders(upload_dir: &UploadDir) -> Result<Vec<FtpDirEntry>> {
+    let archive_path = upload_dir.get_archive_path();
+    Ok(LocalFs
+        .list(&upload_dir.local_path)
+        .await?
+        .into_iter()
+        .map(|listed| listed.entry)
+        .filter(|entry| entry.is_dir() && entry.get_full_path() != archive_path)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(path: &str) -> FtpDirEntry {
+        FtpDirEntry::File(path.to_owned(), 1)
+    }
+
+    #[test]
+    fn upload_pairs_keep_relative_paths_and_send_flags_last() {
+        let upload_dir = UploadDir {
+            local_path: "/data/out".into(),
+            remote_path: "/partner/in".into(),
+            after_upload: AfterUpload::Keep,
+            archive_dir: "uploaded".into(),
+            flag: None,
+            readiness: None,
+        };
+        let order = FtpDirEntry::Folder("/data/out/o".into());
+        let job = Order::for_upload(&order, &upload_dir.remote_path, &upload_dir);
+        let flag = NamePattern::new("*.ok").unwrap();
+        let files = [
+            file("/data/out/o/o.ok"),
+            file("/data/out/o/a.csv"),
+            FtpDirEntry::Folder("/data/out/o/docs".into()),
+            file("/data/out/o/docs/b.pdf"),
+        ];
+        assert_eq!(
+            Uploader::get_upload_pairs(&job, &upload_dir, &flag, &files),
+            vec![
+                ("/data/out/o/a.csv".into(), "/partner/in/o/a.csv".into()),
+                (
+                    "/data/out/o/docs/b.pdf".into(),
+                    "/partner/in/o/docs/b.pdf".into()
+                ),
+                ("/data/out/o/o.ok".into(), "/partner/in/o/o.ok".into()),
+            ]
+        );
+    }
+}
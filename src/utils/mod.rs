@@ -1,5 +1,7 @@
 use ftp_cmd_list_parse::{FtpEntry, FtpEntryKind};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::cmp::Ordering;
+use std::fmt;
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum FtpDirEntry {
@@ -16,9 +18,7 @@ impl Ord for FtpDirEntry {
 
 impl PartialOrd for FtpDirEntry {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        let path = self.get_full_path();
-        let other_path = other.get_full_path();
-        Some(path.cmp(&other_path))
+        Some(self.cmp(other))
     }
 }
 
@@ -32,6 +32,13 @@ impl FtpDirEntry {
     pub fn is_dir(&self) -> bool {
         matches!(self, &FtpDirEntry::Folder(..))
     }
+    ///Size of file, 0 for folders
+    pub fn get_size(&self) -> usize {
+        match &self {
+            FtpDirEntry::File(_, size) => *size,
+            FtpDirEntry::Folder(..) => 0,
+        }
+    }
 }
 
 pub fn categorize_entries(entries: &mut Vec<FtpDirEntry>) -> (Vec<FtpDirEntry>, Vec<FtpDirEntry>) {
@@ -47,35 +54,116 @@ pub fn categorize_entries(entries: &mut Vec<FtpDirEntry>) -> (Vec<FtpDirEntry>,
 }
 
 pub fn parse_ftp_entries(entries: Vec<String>, path: &str) -> Vec<FtpDirEntry> {
-    let mut output: Vec<FtpDirEntry> = Vec::with_capacity(entries.len());
+    parse_ftp_listing(entries, path)
+        .into_iter()
+        .map(|listed| listed.entry)
+        .collect()
+}
+
+/// Directory entry together with the modification date reported by LIST
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListedEntry {
+    pub entry: FtpDirEntry,
+    ///Date as printed by the server, e.g. "Dec 21 2012" or "Dec 21 14:05"
+    pub modified: String,
+}
+impl ListedEntry {
+    ///Last path segment
+    pub fn name(&self) -> String {
+        get_file_name(&self.entry.get_full_path()).to_owned()
+    }
+}
+
+pub fn parse_ftp_listing(entries: Vec<String>, path: &str) -> Vec<ListedEntry> {
+    let mut output: Vec<ListedEntry> = Vec::with_capacity(entries.len());
     for ent in entries {
         if let Some(ftp_entry) = FtpEntry::new(&ent) {
             let name = ftp_entry.name();
-            match ftp_entry.kind() {
-                FtpEntryKind::Directory => {
-                    let dir = FtpDirEntry::Folder(get_absolute_path(path, name));
-                    output.push(dir);
-                }
+            let entry = match ftp_entry.kind() {
+                FtpEntryKind::Directory => FtpDirEntry::Folder(get_absolute_path(path, name)),
                 FtpEntryKind::File => {
-                    let p = get_absolute_path(path, name);
-                    let size = ftp_entry.size();
-                    let file = FtpDirEntry::File(p, size);
-                    output.push(file);
+                    FtpDirEntry::File(get_absolute_path(path, name), ftp_entry.size())
                 }
-                _ => {}
-            }
+                _ => continue,
+            };
+            output.push(ListedEntry {
+                entry,
+                modified: ftp_entry.date_str().to_owned(),
+            });
         }
     }
 
     output
 }
 
+pub fn get_file_name(path: &str) -> &str {
+    path.rsplit('/').next().unwrap_or(path)
+}
+
+/// File name pattern: regex when prefixed with "re:", glob otherwise
+#[derive(Clone)]
+pub enum NamePattern {
+    Glob(glob::Pattern),
+    Regex(regex::Regex),
+}
+impl NamePattern {
+    pub fn new(src: &str) -> std::result::Result<Self, String> {
+        match src.strip_prefix("re:") {
+            Some(re) => regex::Regex::new(re)
+                .map(Self::Regex)
+                .map_err(|e| e.to_string()),
+            None => glob::Pattern::new(src)
+                .map(Self::Glob)
+                .map_err(|e| e.to_string()),
+        }
+    }
+    pub fn matches(&self, name: &str) -> bool {
+        match self {
+            Self::Glob(p) => p.matches(name),
+            Self::Regex(re) => re.is_match(name),
+        }
+    }
+}
+impl fmt::Display for NamePattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Glob(p) => write!(f, "{}", p.as_str()),
+            Self::Regex(re) => write!(f, "re:{}", re.as_str()),
+        }
+    }
+}
+impl fmt::Debug for NamePattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.to_string())
+    }
+}
+impl Serialize for NamePattern {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+impl<'de> Deserialize<'de> for NamePattern {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        let src = String::deserialize(deserializer)?;
+        NamePattern::new(&src).map_err(serde::de::Error::custom)
+    }
+}
+
 fn get_absolute_path(path: &str, name: &str) -> String {
     path.chars()
         .chain("/".chars())
         .chain(name.chars())
         .collect()
 }
+///Local path the remote file is downloaded to: its path below the ftp root, inside `target_folder`
+pub fn get_local_path(ftp_full_path: &str, target_folder: &str) -> String {
+    let ftp_root: String = ftp_full_path
+        .chars()
+        .take_while(|ch| *ch != '/')
+        .chain("/".chars())
+        .collect();
+    get_download_target_path(ftp_full_path, target_folder, &ftp_root)
+}
 pub fn get_download_target_path(
     ftp_full_path: &str,
     target_folder: &str,
@@ -88,3 +176,80 @@ pub fn get_download_target_path(
         .chain(order_path.chars())
         .collect()
 }
+
+///Modification time of a local file, unix seconds
+pub fn modified_secs(meta: &std::fs::Metadata) -> Option<i64> {
+    meta.modified()
+        .ok()
+        .and_then(|m| m.duration_since(std::time::UNIX_EPOCH).ok())
+        .map(|d| d.as_secs() as i64)
+}
+
+///Give local file the modification time (unix seconds) of its remote original
+pub async fn set_modified_secs(path: &str, secs: i64) -> std::io::Result<()> {
+    let file = tokio::fs::OpenOptions::new().write(true).open(path).await?;
+    let file = file.into_std().await;
+    let time = std::time::UNIX_EPOCH + std::time::Duration::from_secs(secs.max(0) as u64);
+    tokio::task::spawn_blocking(move || file.set_modified(time))
+        .await
+        .map_err(std::io::Error::other)?
+}
+
+///Parse modification date reported by a listing into unix seconds (UTC assumed);
+/// Accepts unix listing dates ("Jan 1 12:00" - within the last year, "Jan 1 2020"),
+/// msdos listing dates ("2020-01-15T10:30") and plain seconds (local folders)
+pub fn parse_listing_date(date: &str) -> Option<i64> {
+    use chrono::{Datelike, NaiveDate, NaiveDateTime, Utc};
+    if let Ok(secs) = date.parse::<i64>() {
+        return Some(secs);
+    }
+    if let Ok(dt) = NaiveDateTime::parse_from_str(date, "%Y-%m-%dT%H:%M") {
+        return Some(dt.and_utc().timestamp());
+    }
+    if let Ok(d) = NaiveDate::parse_from_str(date, "%b %d %Y") {
+        return d.and_hms_opt(0, 0, 0).map(|dt| dt.and_utc().timestamp());
+    }
+    //year is omitted for dates within the last 6 months
+    let now = Utc::now();
+    let parse = |year: i32| {
+        NaiveDateTime::parse_from_str(&format!("{} {}", year, date), "%Y %b %d %H:%M")
+            .ok()
+            .map(|dt| dt.and_utc())
+    };
+    match parse(now.year()) {
+        Some(dt) if dt > now + chrono::Duration::days(1) => parse(now.year() - 1),
+        dt => dt,
+    }
+    .map(|dt| dt.timestamp())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    #[test]
+    fn parses_seconds_msdos_and_dated_listings() {
+        assert_eq!(parse_listing_date("1579084200"), Some(1579084200));
+        assert_eq!(parse_listing_date("2020-01-15T10:30"), Some(1579084200));
+        assert_eq!(parse_listing_date("Jan 15 2020"), Some(1579046400));
+    }
+
+    #[test]
+    fn listing_date_without_year_is_within_the_last_year() {
+        let hour_ago = Utc::now() - chrono::Duration::hours(1);
+        let parsed = parse_listing_date(&hour_ago.format("%b %d %H:%M").to_string()).unwrap();
+        assert!((parsed - hour_ago.timestamp()).abs() < 60);
+        //a week ahead can only be last year's date
+        let week_ahead = Utc::now() + chrono::Duration::days(7);
+        let parsed = parse_listing_date(&week_ahead.format("%b %d %H:%M").to_string()).unwrap();
+        assert!(parsed < Utc::now().timestamp());
+        assert!(parsed > (Utc::now() - chrono::Duration::days(366)).timestamp());
+    }
+
+    #[test]
+    fn unknown_listing_date_is_none() {
+        assert_eq!(parse_listing_date(""), None);
+        assert_eq!(parse_listing_date("yesterday"), None);
+    }
+}
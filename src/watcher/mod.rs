@@ -1,31 +1,161 @@
-pub use crate::Config;
-pub use crate::Controller;
-pub use crate::CustomError;
+use crate::journal::{self, Journal, JournalRecord, ResumedOrder};
+use crate::status::{self, LiveCounters, Recent};
+pub use crate::ConnectionRole;
 pub use crate::FtpDirEntry;
 pub use crate::Order;
 pub use crate::Pool;
+pub use crate::ReadinessStrategy;
+use crate::{logger, metrics};
+use crate::{
+    state, utils, CommandHooks, Config, CustomError, ErrorKind, Event, EventStream, FailurePolicy,
+    HookResult, Hooks, ListedEntry, OrderHooks, OrderProgress, OrderState, OrderSummary, Paused,
+    Progress, ScanStatus, Scheduler, Status, SyncMode, SyncState, WatchDir, Work, WorkerConnection,
+};
 pub use crate::{Connection, Result};
-use std::sync::Arc;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{
+        atomic::{AtomicBool, Ordering as AtomicOrdering},
+        Arc,
+    },
+    time::Duration,
+};
 use tokio::{
     join, spawn,
-    sync::{Mutex, MutexGuard},
+    sync::{broadcast, Mutex, MutexGuard, Notify, Semaphore},
+    time::Instant,
 };
+use tracing::{debug, error, field::Empty, info, info_span, warn, Instrument, Span};
 
 #[derive(Debug)]
 pub struct Watcher {
     connections_pool: Arc<Pool>,
-    pending_downloads: Mutex<Vec<Order>>,
+    pending_downloads: Mutex<Scheduler>,
     running_downloads: Mutex<Vec<Order>>,
+    ///Orders waiting for retry backoff
+    failed_orders: Mutex<Vec<Order>>,
+    ///Orders that exhausted their retries, kept until requeued manually
+    dead_letters: Mutex<Vec<Order>>,
+    ///Caps file transfers running at once over all orders
+    transfer_slots: Semaphore,
+    readiness: Arc<dyn ReadinessStrategy>,
+    hooks: Hooks,
+    ///Strategies of watched folders with their own readiness rules, by folder path
+    dir_readiness: std::sync::Mutex<HashMap<String, Arc<dyn ReadinessStrategy>>>,
+    sync_state: SyncState,
+    journal: Journal,
+    ///Transfer progress of downloading orders, by order path
+    progress: std::sync::Mutex<HashMap<String, Arc<OrderProgress>>>,
+    ///Last scan of every watched folder, by folder path
+    scans: std::sync::Mutex<HashMap<String, ScanStatus>>,
+    recent_completed: std::sync::Mutex<Recent>,
+    recent_failed: std::sync::Mutex<Recent>,
+    counters: LiveCounters,
+    paused: std::sync::Mutex<Paused>,
+    ///Wakes the remote watcher before its next tick
+    rescan: Notify,
+    ///Cancel signals of downloading orders, by order path
+    cancels: std::sync::Mutex<HashMap<String, Arc<Notify>>>,
+    ///Ready orders delivered already, reported once while they stay on the server
+    already_delivered: std::sync::Mutex<HashSet<String>>,
+    ///Metrics label of the server account
+    profile: String,
 }
 
 impl Watcher {
     pub async fn with_pool(pool: Pool) -> Result<Watcher> {
+        let config = pool.get_config();
+        let readiness = config.get_readiness().build();
+        //resume orders interrupted by restart or crash
+        let (journal, resumed) = Journal::open(config.get_journal_file_path())?;
+        let (dead_letters, pending): (Vec<Order>, Vec<Order>) = resumed
+            .into_iter()
+            .map(|resumed| Watcher::resume_order(&config, resumed))
+            .partition(|job| job.get_state() == OrderState::DeadLettered);
+        let mut hooks = Hooks::default();
+        if !config.hooks.is_empty() {
+            hooks.add(Arc::new(CommandHooks(config.hooks.to_owned())));
+        }
+        let mut scheduler = Scheduler::new(config.scheduler.to_owned());
+        for job in pending {
+            scheduler.push(job);
+        }
+        let max_transfers = match config.parallel.max_transfers {
+            0 => Semaphore::MAX_PERMITS,
+            n => n,
+        };
         Ok(Watcher {
+            transfer_slots: Semaphore::new(max_transfers),
+            readiness,
+            hooks,
+            dir_readiness: std::sync::Mutex::new(HashMap::new()),
+            sync_state: SyncState::load(config.get_state_file_path()),
+            journal,
             connections_pool: Arc::new(pool),
-            pending_downloads: Mutex::new(Vec::new()),
+            pending_downloads: Mutex::new(scheduler),
             running_downloads: Mutex::new(Vec::new()),
+            progress: std::sync::Mutex::new(HashMap::new()),
+            scans: std::sync::Mutex::new(HashMap::new()),
+            recent_completed: std::sync::Mutex::new(Recent::default()),
+            recent_failed: std::sync::Mutex::new(Recent::default()),
+            counters: LiveCounters::default(),
+            paused: std::sync::Mutex::new(Paused::default()),
+            rescan: Notify::new(),
+            cancels: std::sync::Mutex::new(HashMap::new()),
+            already_delivered: std::sync::Mutex::new(HashSet::new()),
+            profile: config.get_profile(),
+            failed_orders: Mutex::new(Vec::new()),
+            dead_letters: Mutex::new(dead_letters),
         })
     }
+    ///Rebuild order from journal: already downloaded files are skipped by size,
+    /// already deleted remote entries are not touched again
+    fn resume_order(config: &Config, resumed: ResumedOrder) -> Order {
+        info!(
+            order = %resumed.order,
+            downloaded = resumed.downloaded,
+            removed_remotely = resumed.deleted.len(),
+            "resuming order"
+        );
+        let configured = config.get_watch_dir(&resumed.watch_dir);
+        let mut watch_dir = match &configured {
+            Some(watch_dir) => watch_dir.to_owned(),
+            None => {
+                warn!(
+                    order = %resumed.order,
+                    watch_dir = %resumed.watch_dir,
+                    "watched folder is not configured anymore, remote data of the order is kept"
+                );
+                WatchDir::new(&resumed.watch_dir)
+            }
+        };
+        if let Some(sync_mode) = resumed.sync_mode {
+            watch_dir.sync_mode = sync_mode;
+        }
+        let root = match resumed.file_order {
+            true => FtpDirEntry::File(
+                resumed.order.to_owned(),
+                resumed.files.iter().map(|file| file.size).sum(),
+            ),
+            false => FtpDirEntry::Folder(resumed.order.to_owned()),
+        };
+        let mut job = Order::new(&root, &resumed.target, &watch_dir);
+        job.set_accepted();
+        job.set_keep_remote(configured.is_none());
+        job.set_entries(resumed.remaining_files(), resumed.remaining_folders());
+        job.set_modified(resumed.modified());
+        job.set_excluded(resumed.remaining_excluded());
+        //archived order root and companion files
+        job.set_cleaned(resumed.deleted.to_owned());
+        job.set_manifest(resumed.manifest.to_owned());
+        job.set_failures(resumed.attempts, resumed.last_error.to_owned());
+        job.set_state(if resumed.dead_lettered {
+            OrderState::DeadLettered
+        } else {
+            OrderState::Queued
+        });
+        job
+    }
     /// This function will never return;
     /// Watcher maintains pending queue and pool of connections;
     /// It loops infinitely throug pending queue and processes contained tasks;
@@ -36,40 +166,376 @@ impl Watcher {
         let watcher_handle = self.spawn_ftp_watcher_worker();
         let (_, _) = join!(watcher_handle, downloader_handle);
     }
+    ///Replace readiness strategy built from config with a custom one;
+    /// Watched folders with their own readiness rules keep them
+    pub fn set_readiness_strategy(&mut self, strategy: Arc<dyn ReadinessStrategy>) {
+        self.readiness = strategy;
+    }
+    ///Register callbacks on order lifecycle, called after the ones registered before
+    pub fn add_hooks(&mut self, hooks: Arc<dyn OrderHooks>) {
+        self.hooks.add(hooks);
+    }
+    ///Readiness strategy of orders found in the watched folder;
+    /// Built once per folder, so stateful strategies keep counting between scans
+    fn readiness_for(&self, watch_dir: &WatchDir) -> Arc<dyn ReadinessStrategy> {
+        if !watch_dir.has_own_readiness() {
+            return self.readiness.clone();
+        }
+        self.dir_readiness
+            .lock()
+            .expect("readiness lock poisoned")
+            .entry(watch_dir.path.to_owned())
+            .or_insert_with(|| {
+                self.connections_pool
+                    .get_config()
+                    .get_watch_dir_readiness(watch_dir)
+                    .build()
+            })
+            .clone()
+    }
+    ///Snapshot of orders that exhausted their retries
+    pub async fn dead_letters(&self) -> Vec<Order> {
+        self.dead_letters.lock().await.clone()
+    }
+    ///Snapshot of failed orders waiting for their next attempt
+    pub async fn failed_orders(&self) -> Vec<Order> {
+        self.failed_orders.lock().await.clone()
+    }
+    ///Transfer progress of orders being downloaded
+    pub fn progress(&self) -> Vec<Progress> {
+        self.progress
+            .lock()
+            .expect("progress lock poisoned")
+            .values()
+            .map(|p| p.snapshot())
+            .collect()
+    }
+    ///Snapshot of orders, connections, scans and counters
+    pub async fn status(&self) -> Status {
+        let pending = self
+            .pending_downloads
+            .lock()
+            .await
+            .iter()
+            .map(OrderSummary::of)
+            .collect();
+        let progress = self.progress();
+        let running = self
+            .running_downloads
+            .lock()
+            .await
+            .iter()
+            .map(|job| {
+                let mut summary = OrderSummary::of(job);
+                summary.progress = progress.iter().find(|p| p.order == summary.order).cloned();
+                summary
+            })
+            .collect();
+        let summaries = |orders: &[Order]| orders.iter().map(OrderSummary::of).collect();
+        let retrying = summaries(&self.failed_orders.lock().await);
+        let dead_letters = summaries(&self.dead_letters.lock().await);
+        let mut scans: Vec<ScanStatus> = self
+            .scans
+            .lock()
+            .expect("scans lock poisoned")
+            .values()
+            .cloned()
+            .collect();
+        scans.sort_by(|a, b| a.watch_dir.cmp(&b.watch_dir));
+        Status {
+            at: status::now(),
+            circuit: self.connections_pool.circuit_state(),
+            auth_failure: self.connections_pool.get_auth_failure(),
+            pending,
+            running,
+            retrying,
+            dead_letters,
+            recent_completed: self
+                .recent_completed
+                .lock()
+                .expect("recent orders lock poisoned")
+                .to_vec(),
+            recent_failed: self
+                .recent_failed
+                .lock()
+                .expect("recent orders lock poisoned")
+                .to_vec(),
+            connections: self.connections_pool.connection_status(),
+            scans,
+            counters: self.counters.snapshot(),
+            paused: self.get_paused(),
+        }
+    }
+    ///Every metric in Prometheus text format, pool and queue gauges are taken now
+    #[cfg(feature = "metrics")]
+    pub async fn metrics(&self) -> String {
+        metrics::set_status(&self.profile, &self.status().await);
+        metrics::encode()
+    }
+    ///Pause work everywhere (`watch_dir` is None) or in a single watched folder;
+    /// Running downloads are not affected, see `cancel`
+    pub fn pause(&self, work: Work, watch_dir: Option<&str>) {
+        info!(?work, watch_dir, "paused");
+        self.lock_paused().set(work, watch_dir, true);
+    }
+    ///Resume work paused with `pause`; resumed scanning starts right away
+    pub fn resume(&self, work: Work, watch_dir: Option<&str>) {
+        info!(?work, watch_dir, "resumed");
+        self.lock_paused().set(work, watch_dir, false);
+        if work == Work::Scanning {
+            self.rescan();
+        }
+    }
+    pub fn get_paused(&self) -> Paused {
+        self.lock_paused().to_owned()
+    }
+    fn lock_paused(&self) -> std::sync::MutexGuard<'_, Paused> {
+        self.paused.lock().expect("paused lock poisoned")
+    }
+    ///Scan watched folders now instead of waiting for the next tick
+    pub fn rescan(&self) {
+        self.rescan.notify_one();
+    }
+    ///Stop downloading order: transfers are aborted and the order is dead-lettered,
+    /// so it waits for `retry` while remote data stays intact;
+    /// Returns false if the order is not downloading (orders already verified go on to cleanup)
+    pub fn cancel(&self, order_path: &str) -> bool {
+        match self
+            .cancels
+            .lock()
+            .expect("cancels lock poisoned")
+            .get(order_path)
+        {
+            Some(cancel) => {
+                cancel.notify_one();
+                true
+            }
+            None => false,
+        }
+    }
+    ///Queue failed (without waiting for its backoff) or dead-lettered order again with a fresh set of attempts;
+    /// Returns false if there is no such order
+    pub async fn retry(&self, order_path: &str) -> bool {
+        let failed = {
+            let mut failed = self.failed_orders.lock().await;
+            failed
+                .iter()
+                .position(|job| job.get_root_path() == order_path)
+                .map(|i| failed.remove(i))
+        };
+        match failed {
+            Some(job) => {
+                self.requeue(job).await;
+                true
+            }
+            None => self.requeue_dead_letter(order_path).await,
+        }
+    }
+    ///Change priority of a queued order (higher goes first, rules give 0 by default);
+    /// Returns false if the order is not queued
+    pub async fn set_priority(&self, order_path: &str, priority: i32) -> bool {
+        let mut pending = self.pending_downloads.lock().await;
+        match pending.remove(order_path) {
+            Some(job) => {
+                pending.push_with_priority(job, priority);
+                true
+            }
+            None => false,
+        }
+    }
+    ///Move dead-lettered order back to pending queue with a fresh set of attempts;
+    /// Returns false if there is no such dead-lettered order
+    pub async fn requeue_dead_letter(&self, order_path: &str) -> bool {
+        let job = {
+            let mut dead_letters = self.dead_letters.lock().await;
+            match dead_letters
+                .iter()
+                .position(|j| j.get_root_path() == order_path)
+            {
+                Some(i) => dead_letters.remove(i),
+                None => return false,
+            }
+        };
+        self.requeue(job).await;
+        true
+    }
+    async fn requeue(&self, mut job: Order) {
+        job.requeue();
+        //rejected by order_ready hook: the next scan checks it again, hook included
+        if !job.is_accepted() {
+            job.set_state(OrderState::Discovered);
+            self.emit_state(&job);
+            return;
+        }
+        self.emit_state(&job);
+        self.journal.append(JournalRecord::Requeued {
+            order: job.get_root_path(),
+        });
+        self.push_pending(job).await;
+    }
+    ///Subscribe to engine events: connections, scans, order states, downloaded files, errors
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.connections_pool.subscribe()
+    }
+    ///Engine events as a stream, missed events are reported as `Event::Lagged`
+    pub fn events(&self) -> EventStream {
+        self.connections_pool.events()
+    }
+    fn emit_state(&self, job: &Order) {
+        self.connections_pool.emit(Event::OrderStateChanged {
+            order: job.get_root_path(),
+            state: job.get_state(),
+        });
+    }
     ///Get arc to connections pool
     pub fn get_pool_ref(&self) -> Arc<Pool> {
         self.connections_pool.clone()
     }
-    ///Get free connection from pool as mutex guard
+    ///Get free transfer connection from pool as mutex guard
     pub async fn get_connection(&'static self) -> Result<MutexGuard<'static, Connection>> {
         self.connections_pool.get_free_connection().await
     }
+    ///Get free control connection from pool as mutex guard;
+    /// Used for scanning and readiness checks so they never wait behind downloads
+    pub async fn get_control_connection(&'static self) -> Result<MutexGuard<'static, Connection>> {
+        self.connections_pool
+            .get_free_connection_for(ConnectionRole::Control)
+            .await
+    }
 
-    ///Returns list of all found subfolders at remote root folders (can be many, provided by config);
-    /// These are considered to be job units (Orders);
-    /// Only subfolders, without recursive walkthrough;
-    async fn get_root_subfolders(conn: &mut MutexGuard<'_, Connection>) -> Vec<FtpDirEntry> {
-        let mut folders_list = Vec::new();
-        let root_folders = Watcher::get_watch_list(conn);
-        for root in root_folders {
-            if let Ok(folders) = conn.get_ftp_entries(&root).await {
-                for folder in folders {
-                    if folder.is_dir() {
-                        folders_list.push(folder);
+    ///Lists every watched folder down to its order depth;
+    /// Returns listings of the folders orders are found in, these are job units (Orders) and their siblings;
+    /// Roots that could not be listed completely are reported as errors, so nothing is concluded about their orders
+    /// Every scanned folder is reported as an event
+    async fn scan_watch_dirs(
+        &self,
+        conn: &mut MutexGuard<'_, Connection>,
+    ) -> Vec<(WatchDir, Result<Vec<Vec<ListedEntry>>>)> {
+        let mut output = Vec::new();
+        let paused = self.get_paused();
+        for root in Watcher::get_watch_list(conn) {
+            if paused.is_paused_in(Work::Scanning, &root.path) {
+                continue;
+            }
+            let span = info_span!(
+                "scan",
+                watch_dir = %root.path,
+                orders_found = Empty,
+                error = Empty,
+                reply_code = Empty,
+                otel.status_code = Empty
+            );
+            let started = Instant::now();
+            let listings = Watcher::scan_watch_dir(conn, &root)
+                .instrument(span.clone())
+                .await;
+            let mut scan = ScanStatus {
+                watch_dir: root.path.to_owned(),
+                at: status::now(),
+                duration_ms: started.elapsed().as_millis() as u64,
+                orders_found: 0,
+                error: None,
+            };
+            LiveCounters::add(&self.counters.scans, 1);
+            metrics::scan_finished(
+                &self.profile,
+                &root.path,
+                started.elapsed(),
+                listings.is_ok(),
+            );
+            match &listings {
+                Ok(listings) => {
+                    scan.orders_found = listings
+                        .iter()
+                        .map(|l| Watcher::get_order_entries(&root, l).len())
+                        .sum();
+                    span.record("orders_found", scan.orders_found as i64);
+                    self.connections_pool.emit(Event::ScanCompleted {
+                        watch_dir: root.path.to_owned(),
+                        orders_found: scan.orders_found,
+                        duration_ms: scan.duration_ms,
+                    })
+                }
+                Err(e) => {
+                    LiveCounters::add(&self.counters.scan_errors, 1);
+                    scan.error = Some(format!("{:?}", e));
+                    logger::record_error(&span, e);
+                    self.connections_pool.emit(Event::error(e, None))
+                }
+            }
+            self.scans
+                .lock()
+                .expect("scans lock poisoned")
+                .insert(root.path.to_owned(), scan);
+            output.push((root, listings));
+        }
+        output
+    }
+    async fn scan_watch_dir(
+        conn: &mut MutexGuard<'_, Connection>,
+        root: &WatchDir,
+    ) -> Result<Vec<Vec<ListedEntry>>> {
+        let archive_path = root.get_archive_path();
+        let mut level = vec![root.path.to_owned()];
+        //walk down to the folders that contain orders
+        for _ in 1..root.depth.max(1) {
+            let mut next = Vec::new();
+            for path in level {
+                for listed in conn.get_dir_listing(&path).await? {
+                    let path = listed.entry.get_full_path();
+                    if listed.entry.is_dir() && !Watcher::is_archive(root, &path, &archive_path) {
+                        next.push(path);
                     }
                 }
             }
+            level = next;
         }
-        folders_list
+        let mut listings = Vec::with_capacity(level.len());
+        for path in level {
+            listings.push(conn.get_dir_listing(&path).await?);
+        }
+        Ok(listings)
+    }
+    ///Orders among the entries of a scanned folder
+    fn get_order_entries(root: &WatchDir, listing: &[ListedEntry]) -> Vec<FtpDirEntry> {
+        let archive_path = root.get_archive_path();
+        listing
+            .iter()
+            .map(|listed| &listed.entry)
+            .filter(|entry| entry.is_dir() != root.file_orders)
+            //archived orders are not orders anymore
+            .filter(|entry| !Watcher::is_archive(root, &entry.get_full_path(), &archive_path))
+            //companion files are not orders of their own
+            .filter(|entry| {
+                !root.file_orders
+                    || root
+                        .companion
+                        .as_ref()
+                        .is_none_or(|suffix| !entry.get_full_path().ends_with(suffix.as_str()))
+            })
+            //single-file orders pass the same filter as files of folder orders
+            .filter(|entry| match entry {
+                FtpDirEntry::File(path, size) if root.file_orders => {
+                    let parent = path.rsplit_once('/').map_or("", |(parent, _)| parent);
+                    root.filter.accepts(parent, path, *size)
+                }
+                _ => true,
+            })
+            .cloned()
+            .collect()
+    }
+    fn is_archive(root: &WatchDir, path: &str, archive_path: &str) -> bool {
+        root.sync_mode == SyncMode::Archive && path == archive_path
     }
     ///Gets list of folders to watch for job units (Orders);
     /// Provided by config;
-    fn get_watch_list(conn: &MutexGuard<Connection>) -> Vec<String> {
-        conn.get_watch_list()
+    fn get_watch_list(conn: &MutexGuard<Connection>) -> Vec<WatchDir> {
+        conn.get_config().get_watch_dirs()
     }
     ///Spawns task that starts infinite loop checking ftp root for folders;
     /// For every found folder - check if it is finished being written (and thus can be downloaded safely);
-    /// The folder considered ready for download when it contains *.txt file (down one step, not further in subfolders tree);
+    /// The folder considered ready for download when the readiness strategy says so (by default when it contains
+    /// a file with `ready_flag_file_ext` extension, down one step, not further in subfolders tree);
     /// If so => put it in pending downloads queue;
     fn spawn_ftp_watcher_worker(&'static self) -> tokio::task::JoinHandle<()> {
         spawn(async move {
@@ -81,37 +547,236 @@ impl Watcher {
             //check if folder is ready to be downloaded
             //if so => put it in pending queue
             loop {
-                if let Ok(mut conn) = self.get_connection().await {
-                    println!(
-                        "Remote watcher got connection from pool! checking ftp root folders..."
-                    );
-                    let subfolders = Watcher::get_root_subfolders(&mut conn).await;
+                if self.get_paused().is_paused(Work::Scanning) {
+                    debug!("scanning paused");
+                } else if let Ok(mut conn) = self.get_control_connection().await {
+                    debug!("remote watcher got connection, checking watched folders");
+                    let scanned = self.scan_watch_dirs(&mut conn).await;
                     let download_target_folder = conn.get_local_folder_path();
+                    let mut found_orders = HashSet::new();
 
-                    //loop through all found subfolders
-                    for folder in subfolders {
-                        let mut job = Order::new(&folder, &download_target_folder);
-                        let is_running = self.running_downloads.lock().await.contains(&job);
-                        let is_pending = self.pending_downloads.lock().await.contains(&job);
-                        //check if is ready to be downloaded
-                        if !is_running && !is_pending && job.is_ready_for_download(&mut conn).await
-                        {
-                            //finalize job creation & push to pending queue
-                            //if fails - job will be processed on next iteration
-                            if let Ok(()) = job.read_all_entries(&mut conn).await {
-                                self.push_pending(job).await;
+                    for (watch_dir, listings) in scanned {
+                        let listings = match listings {
+                            Ok(listings) => listings,
+                            Err(_) => continue,
+                        };
+                        if watch_dir.sync_mode == SyncMode::Mirror {
+                            let found: Vec<FtpDirEntry> = listings
+                                .iter()
+                                .flat_map(|l| Watcher::get_order_entries(&watch_dir, l))
+                                .collect();
+                            self.remove_vanished_mirrors(
+                                &watch_dir,
+                                &found,
+                                &download_target_folder,
+                            )
+                            .await;
+                        }
+                        //loop through all found orders
+                        for listing in listings {
+                            for entry in Watcher::get_order_entries(&watch_dir, &listing) {
+                                found_orders.insert(entry.get_full_path());
+                                let job = Order::new(&entry, &download_target_folder, &watch_dir);
+                                self.check_order(job, &listing, &mut conn).await;
                             }
                         }
                     }
                     drop(conn);
+                    self.already_delivered
+                        .lock()
+                        .expect("delivered orders lock poisoned")
+                        .retain(|order| found_orders.contains(order));
                 } else {
                     //if didn't get connection => try again later
-                    println!("ftp watcher couldn't get free connection from pool, repeating...");
+                    debug!("remote watcher couldn't get free connection, repeating");
+                }
+                tokio::select! {
+                    _ = interval.tick() => {}
+                    _ = self.rescan.notified() => {}
                 }
-                interval.tick().await;
             }
         })
     }
+    ///Check whether found order should be downloaded; if so => put it in pending queue
+    async fn check_order(
+        &self,
+        mut job: Order,
+        siblings: &[ListedEntry],
+        conn: &mut MutexGuard<'_, Connection>,
+    ) {
+        let job_path = job.get_root_path();
+        let is_running = self.running_downloads.lock().await.contains(&job);
+        let is_pending = self.pending_downloads.lock().await.contains(&job_path);
+        let is_dead = self.dead_letters.lock().await.contains(&job);
+        if is_running || is_pending || is_dead {
+            return;
+        }
+        //failed orders are retried on their own schedule, dead letters wait for manual requeue;
+        // orders rejected by order_ready hook are checked again once their backoff elapsed
+        let rejected = match self.failed_orders.lock().await.iter().find(|j| **j == job) {
+            Some(j) if !j.is_accepted() && j.is_retry_due() => {
+                Some((j.get_attempts(), j.get_last_error().map(|e| e.to_owned())))
+            }
+            Some(_) => return,
+            None => None,
+        };
+        //copied orders stay on the server, they are fetched once
+        if job.get_sync_mode() == SyncMode::Copy && self.sync_state.contains(&job_path) {
+            return;
+        }
+        //check if is ready to be downloaded
+        let readiness = self.readiness_for(job.get_watch_dir());
+        let source: &mut Connection = conn;
+        match job
+            .is_ready_among(source, readiness.as_ref(), siblings.to_vec())
+            .await
+        {
+            Ok(true) => {}
+            Ok(false) => return,
+            //e.g. unreadable flag file or malformed manifest, the order would wait silently forever
+            Err(e) => {
+                warn!(order = %job_path, error = ?e, "failed to check order readiness");
+                self.connections_pool
+                    .emit(Event::error(&e, Some(&job_path)));
+                return;
+            }
+        }
+        job.set_state(OrderState::Ready);
+        self.emit_state(&job);
+        //finalize job creation & push to pending queue
+        //if fails - job will be processed on next iteration
+        if job.read_all_entries(conn).await.is_err() {
+            return;
+        }
+        //single-file order is dated by the listing of its folder
+        if job.is_file_order() {
+            if let Some(listed) = siblings
+                .iter()
+                .find(|e| e.entry.get_full_path() == job_path)
+            {
+                job.note_modified(listed);
+            }
+        }
+        //companion file goes along with the order
+        if let Some(suffix) = &job.get_watch_dir().companion {
+            let name = format!("{}{}", utils::get_file_name(&job_path), suffix);
+            let mut excluded = job.get_excluded_list().to_vec();
+            excluded.extend(
+                siblings
+                    .iter()
+                    .filter(|e| !e.entry.is_dir() && e.name() == name)
+                    .map(|e| e.entry.to_owned()),
+            );
+            job.set_excluded(excluded);
+        }
+        //exactly this order was delivered already
+        if self
+            .journal
+            .is_completed(&job_path, &job.get_files_list().unwrap_or_default())
+        {
+            self.report_delivered(&job_path);
+            return;
+        }
+        //mirrored order is fetched again only when its remote content changed
+        if job.get_sync_mode() == SyncMode::Mirror {
+            let fetched = self.sync_state.get(&job_path);
+            if fetched.is_some_and(|fetched| state::same_files(&fetched, &job.dated_files())) {
+                return;
+            }
+        }
+        if !job.get_excluded_list().is_empty() {
+            info!(
+                order = %job_path,
+                count = job.get_excluded_list().len(),
+                files = ?job.get_excluded_list(),
+                "files excluded by filter"
+            );
+        }
+        job.set_manifest(readiness.take_manifest(&job_path));
+        if let Some((attempts, last_error)) = rejected {
+            self.failed_orders.lock().await.retain(|j| *j != job);
+            job.set_failures(attempts, last_error);
+        }
+        let hooked = self.hooks.order_ready(&job).await;
+        if self.hook_policy(&job_path, hooked) == FailurePolicy::FailOrder {
+            self.fail_order(job, "order_ready hook failed".into(), ErrorKind::Hook)
+                .await;
+            return;
+        }
+        job.set_accepted();
+        self.journal.append(JournalRecord::Queued {
+            order: job_path,
+            watch_dir: job.get_watch_dir().path.to_owned(),
+            target: job.get_target_path(),
+            files: job.dated_files(),
+            folders: job
+                .get_folders_list()
+                .unwrap_or_default()
+                .iter()
+                .map(|f| f.get_full_path())
+                .collect(),
+            manifest: job.get_manifest().cloned(),
+            excluded: state::file_list(job.get_excluded_list()),
+            sync_mode: Some(job.get_sync_mode()),
+            file_order: job.is_file_order(),
+        });
+        self.already_delivered
+            .lock()
+            .expect("delivered orders lock poisoned")
+            .remove(&job.get_root_path());
+        job.set_state(OrderState::Queued);
+        self.emit_state(&job);
+        LiveCounters::add(&self.counters.orders_discovered, 1);
+        metrics::order_discovered(&self.profile, &job.get_watch_dir().path);
+        self.push_pending(job).await;
+    }
+    ///Report ready order left on the server as it was delivered already, once while it is there
+    fn report_delivered(&self, order: &str) {
+        let first = self
+            .already_delivered
+            .lock()
+            .expect("delivered orders lock poisoned")
+            .insert(order.to_owned());
+        if first {
+            warn!(
+                order = %order,
+                "order with the same content was delivered already, it is left on the server"
+            );
+            self.connections_pool.emit(Event::OrderAlreadyDelivered {
+                order: order.to_owned(),
+            });
+        }
+    }
+    ///Mirror mode: delete local copies of fetched orders that disappeared from the server
+    async fn remove_vanished_mirrors(
+        &self,
+        watch_dir: &WatchDir,
+        subfolders: &[FtpDirEntry],
+        download_target_folder: &str,
+    ) {
+        for order_path in self.sync_state.orders_under(&watch_dir.path) {
+            if subfolders.iter().any(|f| f.get_full_path() == order_path) {
+                continue;
+            }
+            let local_path = utils::get_local_path(&order_path, download_target_folder);
+            info!(order = %order_path, local = %local_path, "mirrored order vanished, removing");
+            //single-file orders are files locally too
+            let removed = match tokio::fs::metadata(&local_path).await {
+                Ok(meta) if meta.is_file() => tokio::fs::remove_file(&local_path).await,
+                _ => tokio::fs::remove_dir_all(&local_path).await,
+            };
+            match removed {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                    warn!(local = %local_path, error = ?e, "failed to remove mirrored order");
+                    continue;
+                }
+                _ => {}
+            }
+            if let Err(e) = self.sync_state.remove(&order_path).await {
+                error!(error = ?e, "failed to save sync state");
+            }
+        }
+    }
     ///Spawns a task that runs infinite loop:
     /// On every loop iteration check queue for pending tasks;
     /// If queue is not empty - get next task and run download process;
@@ -124,11 +789,14 @@ impl Watcher {
             //if any job present => extract, put in running queue
             //spawn task to move files from ftp to local folder
             loop {
+                self.requeue_due_failures().await;
                 //try get free connection from pool of connections:
                 if let Ok(conn) = self.get_connection().await {
-                    println!("Downloader got connection from pool! Checking pending queue..");
+                    debug!("downloader got connection, checking pending queue");
 
-                    if let Some(job) = self.get_pending().await {
+                    if let Some(mut job) = self.get_pending().await {
+                        job.set_state(OrderState::Downloading);
+                        self.emit_state(&job);
                         self.insert_runnning(job.to_owned()).await;
                         self.spawn_move_task(job, conn);
                     } else {
@@ -136,54 +804,460 @@ impl Watcher {
                     }
                 } else {
                     //if can't get free connection => wait for 2 seconds:
-                    println!("Downloader couldn't get free connection from pool, repeating...");
+                    debug!("downloader couldn't get free connection, repeating");
                 }
                 interval.tick().await;
             }
         })
     }
     ///Spawn download task that copies folder (specified by provided Order struct) from ftp to local folder
-    /// then removes it from ftp (if downloaded successfully)
+    /// then (if downloaded successfully) handles remote data according to the sync mode of its watched folder:
+    /// removes it from ftp (move), remembers it (copy, mirror) or moves it to the archive folder (archive)
     fn spawn_move_task(
         &'static self,
-        job: Order,
+        mut job: Order,
         mut conn: MutexGuard<'static, Connection>,
     ) -> tokio::task::JoinHandle<()> {
-        spawn(async move {
-            let job_path = job.get_root_path();
-            //if downloaded successfully
-            if let Ok(files) = job.download(&mut conn).await {
-                //first remove all files from ftp
-                if let Err(e) = conn.batch_delete_remote(&files).await {
-                    println!(
-                        "error while batch removing files: {:?}, error: {:?}",
-                        files, e
-                    );
+        let span = info_span!(
+            "order",
+            path = %job.get_root_path(),
+            watch_dir = %job.get_watch_dir().path,
+            files = job.get_files_list().map_or(0, |f| f.len()) as i64,
+            size = job.get_total_size() as i64,
+            error = Empty,
+            otel.status_code = Empty
+        );
+        spawn(
+            async move {
+                let job_path = job.get_root_path();
+                //cancelling while the hook runs stops the order right when its download starts
+                let cancel = Arc::new(Notify::new());
+                self.cancels
+                    .lock()
+                    .expect("cancels lock poisoned")
+                    .insert(job_path.to_owned(), cancel.clone());
+                let started = self.hooks.download_started(&job).await;
+                if self.hook_policy(&job_path, started) == FailurePolicy::FailOrder {
+                    self.cancels
+                        .lock()
+                        .expect("cancels lock poisoned")
+                        .remove(&job_path);
+                    drop(conn);
+                    self.fail_order(job, "download_started hook failed".into(), ErrorKind::Hook)
+                        .await;
+                    return;
+                }
+                //spread the order over idle transfer connections too
+                let per_order = self
+                    .connections_pool
+                    .get_config()
+                    .parallel
+                    .connections_per_order;
+                let helpers = self.connections_pool.try_get_free_connections(
+                    ConnectionRole::Transfer,
+                    per_order.saturating_sub(1),
+                );
+                //helpers go back to the pool as soon as their worker is done
+                let conns = std::iter::once(Box::new(&mut *conn) as WorkerConnection)
+                    .chain(helpers.into_iter().map(|c| Box::new(c) as WorkerConnection))
+                    .collect();
+                let keep_remote = AtomicBool::new(false);
+                let progress = Arc::new(OrderProgress::new(
+                    &job_path,
+                    &job.get_files_list().unwrap_or_default(),
+                ));
+                self.progress
+                    .lock()
+                    .expect("progress lock poisoned")
+                    .insert(job_path.to_owned(), progress.clone());
+                let download =
+                    job.download_parallel(conns, &self.transfer_slots, &progress, |file| {
+                        let (job, job_path, keep_remote) = (&job, &job_path, &keep_remote);
+                        async move {
+                            LiveCounters::add(&self.counters.files_downloaded, 1);
+                            metrics::file_downloaded(
+                                &self.profile,
+                                &job.get_watch_dir().path,
+                                file.get_size() as u64,
+                            );
+                            LiveCounters::add(
+                                &self.counters.bytes_downloaded,
+                                file.get_size() as u64,
+                            );
+                            self.journal.append(JournalRecord::FileDownloaded {
+                                order: job_path.to_owned(),
+                                path: file.get_full_path(),
+                            });
+                            self.connections_pool.emit(Event::FileDownloaded {
+                                order: job_path.to_owned(),
+                                path: file.get_full_path(),
+                                size: file.get_size(),
+                            });
+                            let hooked = self.hooks.file_downloaded(job, &file).await;
+                            match self.hook_policy(job_path, hooked) {
+                                FailurePolicy::FailOrder => {
+                                    Err(CustomError::Io("file_downloaded hook failed".into()))
+                                }
+                                FailurePolicy::KeepRemote => {
+                                    keep_remote.store(true, AtomicOrdering::Relaxed);
+                                    Ok(())
+                                }
+                                FailurePolicy::Ignore => Ok(()),
+                            }
+                        }
+                    });
+                let report_interval = Duration::from_millis(
+                    self.connections_pool
+                        .get_config()
+                        .progress_interval_ms
+                        .max(1),
+                );
+                let report = async {
+                    let mut interval = tokio::time::interval(report_interval);
+                    interval.tick().await;
+                    loop {
+                        interval.tick().await;
+                        self.connections_pool
+                            .emit(Event::Progress(progress.snapshot()));
+                    }
+                };
+                let downloaded = tokio::select! {
+                    downloaded = download => Some(downloaded),
+                    _ = cancel.notified() => None,
+                    _ = report => unreachable!("progress reporting never ends"),
+                };
+                self.cancels
+                    .lock()
+                    .expect("cancels lock poisoned")
+                    .remove(&job_path);
+                self.connections_pool
+                    .emit(Event::Progress(progress.snapshot()));
+                self.progress
+                    .lock()
+                    .expect("progress lock poisoned")
+                    .remove(&job_path);
+                let downloaded = match downloaded {
+                    Some(downloaded) => downloaded,
+                    None => {
+                        //aborted transfers leave replies pending on control channels, reconnect them;
+                        // helpers were marked when their transfer was dropped
+                        conn.set_degraded(true);
+                        drop(conn);
+                        Span::current().record("error", "cancelled");
+                        self.cancel_order(job).await;
+                        return;
+                    }
+                };
+                let files = match downloaded {
+                    Ok(files) => files,
+                    Err(e) => {
+                        drop(conn);
+                        self.fail_order(job, format!("download failed: {:?}", e), e.kind())
+                            .await;
+                        return;
+                    }
+                };
+                //never delete remote data that does not match the manifest
+                self.set_running_state(&job, OrderState::Verifying).await;
+                if let Err(e) = job.verify_downloaded().await {
+                    drop(conn);
+                    self.fail_order(job, format!("verification failed: {:?}", e), e.kind())
+                        .await;
+                    return;
                 }
-                //then remove all folders from ftp
-                let folders = job.get_folders_list().unwrap_or_default();
-                if let Err(e) = conn.batch_delete_remote(&folders).await {
-                    println!("error while removing dir: {:?}, error: {:?}", &job_path, e);
+                self.journal.append(JournalRecord::Downloaded {
+                    order: job_path.to_owned(),
+                });
+                //order landed: downstream processing may veto remote cleanup
+                let completed = self.hooks.order_completed(&job).await;
+                let keep_remote = match self.hook_policy(&job_path, completed) {
+                    FailurePolicy::FailOrder => {
+                        drop(conn);
+                        self.fail_order(job, "order_completed hook failed".into(), ErrorKind::Hook)
+                            .await;
+                        return;
+                    }
+                    FailurePolicy::KeepRemote => true,
+                    FailurePolicy::Ignore => keep_remote.load(AtomicOrdering::Relaxed),
+                };
+                self.set_running_state(&job, OrderState::CleaningUp).await;
+                let mut cleaned = false;
+                let cleanup = match job.get_sync_mode() {
+                    _ if job.keeps_remote() => {
+                        warn!("remote data kept as its watched folder is not configured anymore");
+                        Ok(())
+                    }
+                    SyncMode::Move | SyncMode::Archive if keep_remote => {
+                        warn!("remote data kept as hook failed");
+                        Ok(())
+                    }
+                    SyncMode::Move => {
+                        cleaned = true;
+                        self.delete_remote(&mut job, &files, &mut conn)
+                            .instrument(info_span!("cleanup", mode = "move"))
+                            .await
+                    }
+                    SyncMode::Copy => {
+                        self.record_fetched(&job, &files).await;
+                        Ok(())
+                    }
+                    SyncMode::Mirror => {
+                        self.remove_stale_local_files(&job, &files).await;
+                        self.record_fetched(&job, &files).await;
+                        Ok(())
+                    }
+                    SyncMode::Archive => {
+                        cleaned = true;
+                        self.archive_remote(&mut job, &mut conn)
+                            .instrument(info_span!("cleanup", mode = "archive"))
+                            .await
+                    }
+                };
+                drop(conn);
+                //order stays downloaded in the journal; the retry skips files present locally
+                // and only finishes the cleanup
+                if let Err(e) = cleanup {
+                    self.fail_order(job, format!("remote cleanup failed: {:?}", e), e.kind())
+                        .await;
+                    return;
                 }
+                self.journal.append(JournalRecord::Completed {
+                    order: job_path.to_owned(),
+                    signature: journal::signature(&files),
+                });
                 self.remove_from_runnig(&job).await;
+                LiveCounters::add(&self.counters.orders_completed, 1);
+                metrics::order_completed(&self.profile, &job.get_watch_dir().path);
+                self.recent_completed
+                    .lock()
+                    .expect("recent orders lock poisoned")
+                    .push(&job, OrderState::Done);
+                self.connections_pool.emit(Event::OrderStateChanged {
+                    order: job_path.to_owned(),
+                    state: OrderState::Done,
+                });
+                if cleaned {
+                    let hooked = self.hooks.remote_cleaned(&job).await;
+                    self.hook_policy(&job_path, hooked);
+                }
             }
-        })
+            .instrument(span),
+        )
     }
-    ///Extract next job from queue of pending jobs if any
+    ///Report failed hooks; returns what the failure means for the order
+    fn hook_policy(&self, order: &str, result: HookResult) -> FailurePolicy {
+        match result {
+            Ok(()) => FailurePolicy::Ignore,
+            Err(e) => {
+                warn!(order = %order, error = %e, "hook failed");
+                e.policy
+            }
+        }
+    }
+    ///Archive mode: move the order into the archive folder of its watched folder
+    /// Stops at the first failure; entries moved already are skipped when it is retried
+    async fn archive_remote(&self, job: &mut Order, conn: &mut Connection) -> Result<()> {
+        let job_path = job.get_root_path();
+        let archive_path = job.get_watch_dir().get_archive_path();
+        //excluded files of a folder order moved with it, companion files are next to it
+        let companions = job
+            .get_excluded_list()
+            .iter()
+            .map(|file| file.get_full_path())
+            .filter(|path| !path.starts_with(&format!("{}/", job_path)));
+        let paths: Vec<String> = std::iter::once(job_path.to_owned())
+            .chain(companions)
+            .collect();
+        for path in paths {
+            if job.is_cleaned(&path) {
+                continue;
+            }
+            let target = conn.archive_remote(&path, &archive_path).await?;
+            info!(from = %path, to = %target, "archived");
+            self.journal.append(JournalRecord::RemoteDeleted {
+                order: job_path.to_owned(),
+                path: path.to_owned(),
+            });
+            job.mark_cleaned(path);
+        }
+        Ok(())
+    }
+    ///Take failed order out of running list and either schedule a retry or dead-letter it
+    async fn fail_order(&self, mut job: Order, error: String, kind: ErrorKind) {
+        self.remove_from_runnig(&job).await;
+        let retry = self.connections_pool.get_config().retry;
+        let order = job.get_root_path();
+        //orders fail inside their span
+        let span = Span::current();
+        span.record("error", &error);
+        span.record("otel.status_code", "ERROR");
+        self.connections_pool.emit(Event::Error {
+            kind,
+            message: error.to_owned(),
+            order: Some(order.to_owned()),
+        });
+        let state = job.fail(error.to_owned(), &retry);
+        self.emit_state(&job);
+        LiveCounters::add(&self.counters.orders_failed, 1);
+        metrics::order_failed(&self.profile, &job.get_watch_dir().path);
+        self.recent_failed
+            .lock()
+            .expect("recent orders lock poisoned")
+            .push(&job, state);
+        let record = match state {
+            OrderState::DeadLettered => {
+                error!(
+                    order = %order,
+                    attempts = job.get_attempts(),
+                    error = %error,
+                    "order dead-lettered"
+                );
+                LiveCounters::add(&self.counters.orders_dead_lettered, 1);
+                self.dead_letters.lock().await.push(job.to_owned());
+                JournalRecord::DeadLettered {
+                    order,
+                    attempts: job.get_attempts(),
+                    error: error.to_owned(),
+                }
+            }
+            _ => {
+                warn!(
+                    order = %order,
+                    attempt = job.get_attempts(),
+                    max_attempts = retry.max_attempts,
+                    retry_in = ?retry.delay(job.get_attempts()),
+                    error = %error,
+                    "order failed"
+                );
+                self.failed_orders.lock().await.push(job.to_owned());
+                JournalRecord::Failed {
+                    order,
+                    attempts: job.get_attempts(),
+                    error: error.to_owned(),
+                }
+            }
+        };
+        //orders rejected before they were journaled as queued are found by the scan again
+        if job.is_accepted() {
+            self.journal.append(record);
+        }
+        let hooked = self.hooks.order_failed(&job, &error).await;
+        self.hook_policy(&job.get_root_path(), hooked);
+    }
+    ///Dead-letter cancelled order, without retries or hooks; it waits for `retry`
+    async fn cancel_order(&self, mut job: Order) {
+        self.remove_from_runnig(&job).await;
+        let order = job.get_root_path();
+        info!(order = %order, "order cancelled");
+        job.set_failures(job.get_attempts(), Some("cancelled".into()));
+        job.set_state(OrderState::DeadLettered);
+        self.emit_state(&job);
+        self.recent_failed
+            .lock()
+            .expect("recent orders lock poisoned")
+            .push(&job, OrderState::DeadLettered);
+        self.journal.append(JournalRecord::DeadLettered {
+            order,
+            attempts: job.get_attempts(),
+            error: "cancelled".into(),
+        });
+        self.dead_letters.lock().await.push(job);
+    }
+    ///Move failed orders whose backoff elapsed back to pending queue
+    async fn requeue_due_failures(&self) {
+        let due: Vec<Order> = {
+            let mut failed = self.failed_orders.lock().await;
+            //orders rejected by order_ready hook are taken by the scan instead
+            let (due, waiting) = failed
+                .drain(..)
+                .partition(|job| job.is_accepted() && job.is_retry_due());
+            *failed = waiting;
+            due
+        };
+        for mut job in due {
+            job.set_state(OrderState::Queued);
+            self.emit_state(&job);
+            self.push_pending(job).await;
+        }
+    }
+    ///Update state of running order, so it is visible from outside
+    async fn set_running_state(&self, job: &Order, state: OrderState) {
+        let mut running = self.running_downloads.lock().await;
+        if let Some(j) = running.iter_mut().find(|j| *j == job) {
+            j.set_state(state);
+            self.emit_state(j);
+        }
+    }
+    ///Move mode: remove downloaded order from ftp;
+    /// Files excluded by filter are removed too, otherwise folders could not be removed
+    /// Stops at the first failure; entries deleted already are skipped when it is retried
+    async fn delete_remote(
+        &self,
+        job: &mut Order,
+        files: &[FtpDirEntry],
+        conn: &mut Connection,
+    ) -> Result<()> {
+        let job_path = job.get_root_path();
+        let excluded = job.get_excluded_list().to_vec();
+        let folders = job.get_folders_list().unwrap_or_default();
+        //files first, excluded files then, folders are empty last
+        for batch in [files, &excluded, &folders] {
+            let left: Vec<FtpDirEntry> = batch
+                .iter()
+                .filter(|entry| !job.is_cleaned(&entry.get_full_path()))
+                .cloned()
+                .collect();
+            conn.batch_delete_remote_with(&left, |entry| {
+                LiveCounters::add(&self.counters.remote_entries_deleted, 1);
+                metrics::remote_deleted(&self.profile, &job.get_watch_dir().path);
+                self.journal.append(JournalRecord::RemoteDeleted {
+                    order: job_path.to_owned(),
+                    path: entry.get_full_path(),
+                });
+                job.mark_cleaned(entry.get_full_path());
+            })
+            .await?;
+        }
+        Ok(())
+    }
+    ///Copy and mirror modes: remember what was fetched
+    async fn record_fetched(&self, job: &Order, files: &[FtpDirEntry]) {
+        let files = state::dated_file_list(files, |path| job.get_modified(path));
+        if let Err(e) = self.sync_state.record(&job.get_root_path(), files).await {
+            error!(error = ?e, "failed to save sync state");
+        }
+    }
+    ///Mirror mode: delete local files that were removed from the remote order
+    async fn remove_stale_local_files(&self, job: &Order, files: &[FtpDirEntry]) {
+        let previous = self
+            .sync_state
+            .get(&job.get_root_path())
+            .unwrap_or_default();
+        let current = state::file_list(files);
+        for file in previous {
+            if current.iter().any(|f| f.path == file.path) {
+                continue;
+            }
+            let local_path = job.get_local_path(&file.path);
+            if let Err(e) = tokio::fs::remove_file(&local_path).await {
+                warn!(local = %local_path, error = ?e, "failed to remove stale file");
+            }
+        }
+    }
+    ///Extract next job from queue of pending jobs if any, as the scheduler sees fit
     async fn get_pending(&self) -> Option<Order> {
-        let mut pending = self.pending_downloads.lock().await;
-        if pending.is_empty() {
+        let paused = self.get_paused();
+        if paused.is_paused(Work::Downloading) {
             return None;
         }
-        Some(pending.remove(0))
+        self.pending_downloads
+            .lock()
+            .await
+            .pop_where(|job| !paused.is_paused_in(Work::Downloading, &job.get_watch_dir().path))
     }
     ///Push provided job to pending queue
     async fn push_pending(&self, job: Order) {
-        let mut pending = self.pending_downloads.lock().await;
-        if pending.contains(&job) {
-            return;
-        }
-        pending.push(job);
+        self.pending_downloads.lock().await.push(job);
     }
     ///Push provided job to list of running jobs
     async fn insert_runnning(&self, job: Order) {
@@ -199,3 +1273,35 @@ impl Watcher {
         *running = running.clone().into_iter().filter(|j| j != job).collect();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::NamePattern;
+
+    fn file(path: &str, size: usize) -> ListedEntry {
+        ListedEntry {
+            entry: FtpDirEntry::File(path.to_owned(), size),
+            modified: String::new(),
+        }
+    }
+
+    #[test]
+    fn file_orders_are_filtered() {
+        let mut root = WatchDir::new("/in");
+        root.file_orders = true;
+        root.filter.ignore_hidden = true;
+        root.filter.exclude = vec![NamePattern::new("*.tmp").unwrap()];
+        root.filter.min_size = Some(1);
+        let listing = [
+            file("/in/a.csv", 10),
+            file("/in/.DS_Store", 10),
+            file("/in/b.tmp", 10),
+            file("/in/empty.csv", 0),
+        ];
+        assert_eq!(
+            Watcher::get_order_entries(&root, &listing),
+            vec![FtpDirEntry::File("/in/a.csv".to_owned(), 10)]
+        );
+    }
+}